@@ -3,7 +3,12 @@ use std::fs::create_dir_all;
 
 use cosmwasm_schema::{export_schema, export_schema_with_title, remove_schemas, schema_for};
 
-use loterra_staking::msg::{ConfigResponse, GetHolderResponse, GetAllBondedResponse, HandleMsg, InitMsg, QueryMsg};
+use loterra_staking::msg::{
+    ConfigResponse, EmergencyStatusResponse, GetAllBondedResponse, GetHolderResponse,
+    GetHoldersResponse, HandleMsg, InitMsg, MigrateMsg, PauseResponse, PendingAdminActionsResponse,
+    ProposalResponse, QueryMsg, RewardStreamsResponse, TopStakersResponse, VoteResponse,
+    VotingPowerResponse,
+};
 
 fn main() {
     let mut out_dir = current_dir().unwrap();
@@ -14,6 +19,7 @@ fn main() {
     export_schema(&schema_for!(InitMsg), &out_dir);
     export_schema(&schema_for!(HandleMsg), &out_dir);
     export_schema(&schema_for!(QueryMsg), &out_dir);
+    export_schema(&schema_for!(MigrateMsg), &out_dir);
     export_schema_with_title(&mut schema_for!(ConfigResponse), &out_dir, "ConfigResponse");
     export_schema_with_title(
         &mut schema_for!(GetHolderResponse),
@@ -25,4 +31,41 @@ fn main() {
         &out_dir,
         "GetAllBondedResponse",
     );
+    export_schema_with_title(
+        &mut schema_for!(GetHoldersResponse),
+        &out_dir,
+        "GetHoldersResponse",
+    );
+    export_schema_with_title(
+        &mut schema_for!(TopStakersResponse),
+        &out_dir,
+        "TopStakersResponse",
+    );
+    export_schema_with_title(
+        &mut schema_for!(RewardStreamsResponse),
+        &out_dir,
+        "RewardStreamsResponse",
+    );
+    export_schema_with_title(
+        &mut schema_for!(PendingAdminActionsResponse),
+        &out_dir,
+        "PendingAdminActionsResponse",
+    );
+    export_schema_with_title(
+        &mut schema_for!(EmergencyStatusResponse),
+        &out_dir,
+        "EmergencyStatusResponse",
+    );
+    export_schema_with_title(&mut schema_for!(PauseResponse), &out_dir, "PauseResponse");
+    export_schema_with_title(
+        &mut schema_for!(VotingPowerResponse),
+        &out_dir,
+        "VotingPowerResponse",
+    );
+    export_schema_with_title(
+        &mut schema_for!(ProposalResponse),
+        &out_dir,
+        "ProposalResponse",
+    );
+    export_schema_with_title(&mut schema_for!(VoteResponse), &out_dir, "VoteResponse");
 }
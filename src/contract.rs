@@ -1,36 +1,370 @@
 use cosmwasm_std::{
-    to_binary, Api, BankMsg, Binary, CanonicalAddr, Coin, CosmosMsg, Env, Extern, HandleResponse,
-    HumanAddr, InitResponse, LogAttribute, Order, Querier, StdError, StdResult, Storage, Uint128,
-    WasmMsg,
+    from_binary, to_binary, Api, BankMsg, Binary, CanonicalAddr, Coin, CosmosMsg, Decimal, Env,
+    Extern, HandleResponse, HumanAddr, InitResponse, LogAttribute, MigrateResponse, MigrateResult,
+    Order, Querier, StdError, StdResult, Storage, Uint128, WasmMsg,
 };
 
-use crate::msg::{ConfigResponse, GetBondedResponse, GetHolderResponse,GetAllBondedResponse, HandleMsg, InitMsg, QueryMsg};
+use crate::msg::{
+    ConfigResponse, Cw20ExecuteMsg, Cw20ReceiveMsg, EmergencyStatusResponse, GetAllBondedResponse,
+    GetBondedResponse, GetHolderResponse, GetHoldersResponse, HandleMsg, InitMsg, LegacyMigrateMsg,
+    MigrateMsg, OrderBy, PauseResponse, PendingAdminActionsResponse, ProposalResponse, QueryMsg,
+    ReceiveMsg, RewardStreamsResponse, TokenBalanceResponse, TopStakerResponse, TopStakersResponse,
+    VoteResponse, VotingPowerResponse,
+};
 use crate::state::{
-    config, config_read, staking_storage, staking_storage_read, StakingInfo, State,
+    admin_action_storage, admin_action_storage_read, bonded_index_key, bonded_index_storage,
+    bonded_index_storage_read, config, config_read, contract_info, contract_info_read,
+    expired_lock_storage, expired_lock_storage_read, legacy_config_read,
+    legacy_staking_storage_read, lock_expiry_storage, lock_expiry_storage_read, matured_storage,
+    matured_storage_read, proposal_storage, proposal_storage_read, staking_storage,
+    staking_storage_read, total_voting_power_storage, total_voting_power_storage_read,
+    vote_storage, vote_storage_read, voting_power_storage, voting_power_storage_read,
+    warming_storage, warming_storage_read, AdminAction, ContractInfo, GovernanceConfig,
+    LegacyStakingInfo, LockExpiryBatch, LockInfo, LockTier, MaturedBatch, PauseFlags,
+    PenaltyDestination, PendingAdmin, Proposal, ProposalStatus, RewardIndex, RewardStream,
+    StakingInfo, State, TokenBalance, TokenRewardIndex, UnbondingInfo, Vote, VoteOption,
+    WarmingBatch, WarmingInfo, MAX_DESCRIPTION_LENGTH, MAX_REWARD_STREAMS, MAX_WARMING_ENTRIES,
+    REWARD_INDEX_SCALE,
 };
+use cosmwasm_storage::ReadonlyBucket;
 use std::ops::{Add, Sub};
-use std::io::Stderr;
+
+/// Name stored in the contract info, a migration refuses any other
+pub const CONTRACT_NAME: &str = "crates.io:loterra-staking";
+pub const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Holders per GetHolders page
+pub const DEFAULT_HOLDERS_LIMIT: u32 = 10;
+pub const MAX_HOLDERS_LIMIT: u32 = 30;
 
 pub fn init<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
     msg: InitMsg,
 ) -> StdResult<InitResponse> {
+    let reward_indexes = whitelist_reward_denoms(msg.reward_denoms, &[])?;
+    let token_reward_indexes = whitelist_reward_tokens(&deps.api, msg.reward_tokens, &[])?;
+
+    validate_lock_tiers(&msg.lock_tiers)?;
+    if msg.max_pause_duration == 0 {
+        return Err(StdError::generic_err(
+            "Max pause duration must be at least 1",
+        ));
+    }
+    if msg.max_unbonding_entries == 0 {
+        return Err(StdError::generic_err(
+            "Max unBonding entries must be at least 1",
+        ));
+    }
+    if msg.emergency_withdraw_delay == 0 {
+        return Err(StdError::generic_err(
+            "Emergency withdraw delay must be at least 1",
+        ));
+    }
+    validate_governance(&msg.governance)?;
+
     let state = State {
         admin: deps.api.canonical_address(&env.message.sender)?,
+        guardian: None,
         address_cw20_loterra_smart_contract: deps
             .api
             .canonical_address(&msg.address_cw20_loterra_smart_contract)?,
         unbonded_period: msg.unbonded_period,
-        denom_reward: msg.denom_reward,
-        safe_lock: false,
+        pause: PauseFlags::default(),
+        paused_since: None,
+        pause_expiry: None,
+        max_pause_duration: msg.max_pause_duration,
+        locked_since: None,
+        lock_lapsed_at: None,
+        emergency_withdraw_delay: msg.emergency_withdraw_delay,
+        max_unbonding_entries: msg.max_unbonding_entries,
+        total_bonded: Uint128::zero(),
+        total_weighted: Uint128::zero(),
+        total_unbonding: Uint128::zero(),
+        total_warming: Uint128::zero(),
+        min_bonding_age: msg.min_bonding_age,
+        reward_indexes,
+        token_reward_indexes,
+        reward_streams: vec![],
+        stream_payers: vec![],
+        carry_reward_remainder: msg.carry_reward_remainder,
+        reward_carry: vec![],
+        lock_tiers: msg.lock_tiers,
+        instant_unstake_penalty: Decimal::zero(),
+        penalty_destination: PenaltyDestination::Redistribute,
+        treasury: None,
+        pending_admin: None,
+        timelock_delay: msg.timelock_delay,
+        next_admin_action_id: 0,
+        governance: msg.governance,
+        next_proposal_id: 0,
     };
 
     config(&mut deps.storage).save(&state)?;
+    contract_info(&mut deps.storage).save(&ContractInfo {
+        contract: CONTRACT_NAME.to_string(),
+        version: CONTRACT_VERSION.to_string(),
+    })?;
 
     Ok(InitResponse::default())
 }
 
+pub fn migrate<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    msg: MigrateMsg,
+) -> MigrateResult {
+    let from_version = match contract_info_read(&deps.storage).may_load()? {
+        Some(info) => {
+            if info.contract != CONTRACT_NAME {
+                return Err(StdError::generic_err(format!(
+                    "Can't migrate from contract {}",
+                    info.contract
+                )));
+            }
+            if parse_version(&info.version)? > parse_version(CONTRACT_VERSION)? {
+                return Err(StdError::generic_err(format!(
+                    "Can't downgrade from version {} to {}",
+                    info.version, CONTRACT_VERSION
+                )));
+            }
+            // Every versioned layout is the current one
+            info.version
+        }
+        None => {
+            let legacy = match msg.legacy {
+                Some(legacy) => legacy,
+                None => {
+                    return Err(StdError::generic_err(
+                        "Migrating from the first layout needs the legacy parameters",
+                    ))
+                }
+            };
+            migrate_legacy(deps, env.clone(), legacy)?;
+            "legacy".to_string()
+        }
+    };
+    backfill_voting_power(&mut deps.storage, env.block.height)?;
+    backfill_bonded_index(&mut deps.storage)?;
+
+    contract_info(&mut deps.storage).save(&ContractInfo {
+        contract: CONTRACT_NAME.to_string(),
+        version: CONTRACT_VERSION.to_string(),
+    })?;
+
+    Ok(MigrateResponse {
+        messages: vec![],
+        log: vec![
+            LogAttribute {
+                key: "action".to_string(),
+                value: "migrate".to_string(),
+            },
+            LogAttribute {
+                key: "from_version".to_string(),
+                value: from_version,
+            },
+            LogAttribute {
+                key: "to_version".to_string(),
+                value: CONTRACT_VERSION.to_string(),
+            },
+        ],
+        data: None,
+    })
+}
+
+/// Numeric parts of a major.minor.patch version, comparable in order
+fn parse_version(version: &str) -> StdResult<Vec<u64>> {
+    version
+        .split('.')
+        .map(|part| {
+            part.parse::<u64>()
+                .map_err(|_| StdError::generic_err(format!("Invalid version {}", version)))
+        })
+        .collect()
+}
+
+/// Upgrade the State and every holder from the first layout
+fn migrate_legacy<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    msg: LegacyMigrateMsg,
+) -> StdResult<()> {
+    let legacy = legacy_config_read(&deps.storage)
+        .load()
+        .map_err(|_| StdError::generic_err("Unknown contract state"))?;
+    validate_lock_tiers(&msg.lock_tiers)?;
+    if msg.max_pause_duration == 0 {
+        return Err(StdError::generic_err(
+            "Max pause duration must be at least 1",
+        ));
+    }
+    if msg.max_unbonding_entries == 0 {
+        return Err(StdError::generic_err(
+            "Max unBonding entries must be at least 1",
+        ));
+    }
+    if msg.emergency_withdraw_delay == 0 {
+        return Err(StdError::generic_err(
+            "Emergency withdraw delay must be at least 1",
+        ));
+    }
+    validate_governance(&msg.governance)?;
+
+    // Rewards were settled eagerly, holders start at the zero index
+    let reward_indexes = whitelist_reward_denoms(vec![legacy.denom_reward.clone()], &[])?;
+    let token_reward_indexes = whitelist_reward_tokens(&deps.api, msg.reward_tokens, &[])?;
+    let holders = legacy_staking_storage_read(&deps.storage)
+        .range(None, None, Order::Ascending)
+        .collect::<StdResult<Vec<(Vec<u8>, LegacyStakingInfo)>>>()?;
+    let mut total_bonded = Uint128::zero();
+    let mut total_unbonding = Uint128::zero();
+    for (key, holder) in holders {
+        let mut unbonding = vec![];
+        if !holder.un_bonded.is_zero() {
+            unbonding.push(UnbondingInfo {
+                amount: holder.un_bonded,
+                release_height: holder.period,
+            });
+        }
+        let mut available = vec![];
+        if !holder.available.is_zero() {
+            available.push(Coin {
+                denom: legacy.denom_reward.clone(),
+                amount: holder.available,
+            });
+        }
+        total_bonded = total_bonded.add(holder.bonded);
+        total_unbonding = total_unbonding.add(holder.un_bonded);
+        staking_storage(&mut deps.storage).save(
+            &key,
+            &StakingInfo {
+                bonded: holder.bonded,
+                un_bonded: holder.un_bonded,
+                unbonding,
+                available,
+                reward_indexes: reward_indexes.clone(),
+                available_tokens: vec![],
+                token_reward_indexes: vec![],
+                lock: None,
+                warming: vec![],
+            },
+        )?;
+    }
+
+    let mut state = State {
+        admin: legacy.admin,
+        guardian: None,
+        address_cw20_loterra_smart_contract: legacy.address_cw20_loterra_smart_contract,
+        unbonded_period: legacy.unbonded_period,
+        pause: PauseFlags::default(),
+        paused_since: None,
+        pause_expiry: None,
+        max_pause_duration: msg.max_pause_duration,
+        locked_since: None,
+        lock_lapsed_at: None,
+        emergency_withdraw_delay: msg.emergency_withdraw_delay,
+        max_unbonding_entries: msg.max_unbonding_entries,
+        total_bonded,
+        total_weighted: total_bonded,
+        total_unbonding,
+        total_warming: Uint128::zero(),
+        min_bonding_age: msg.min_bonding_age,
+        reward_indexes,
+        token_reward_indexes,
+        reward_streams: vec![],
+        stream_payers: vec![],
+        carry_reward_remainder: msg.carry_reward_remainder,
+        reward_carry: vec![],
+        lock_tiers: msg.lock_tiers,
+        instant_unstake_penalty: Decimal::zero(),
+        penalty_destination: PenaltyDestination::Redistribute,
+        treasury: None,
+        pending_admin: None,
+        timelock_delay: msg.timelock_delay,
+        next_admin_action_id: 0,
+        governance: msg.governance,
+        next_proposal_id: 0,
+    };
+    if legacy.safe_lock {
+        set_pause(&mut state, PauseFlags::all(), env.block.height);
+    }
+    config(&mut deps.storage).save(&state)
+}
+
+/// Build the reward denom whitelist, denoms already in current keep their index
+fn whitelist_reward_denoms(
+    denoms: Vec<String>,
+    current: &[RewardIndex],
+) -> StdResult<Vec<RewardIndex>> {
+    if denoms.is_empty() {
+        return Err(StdError::generic_err("At least one reward denom required"));
+    }
+    let mut reward_indexes: Vec<RewardIndex> = vec![];
+    for denom in denoms {
+        if reward_indexes.iter().any(|r| r.denom == denom) {
+            return Err(StdError::generic_err(format!(
+                "Duplicate reward denom {}",
+                denom
+            )));
+        }
+        reward_indexes.push(match current.iter().find(|r| r.denom == denom) {
+            Some(reward) => reward.clone(),
+            None => RewardIndex {
+                denom,
+                index: Uint128::zero(),
+                remainder: Uint128::zero(),
+            },
+        });
+    }
+    Ok(reward_indexes)
+}
+
+/// Build the cw-20 reward token whitelist, tokens already in current keep their index
+fn whitelist_reward_tokens<A: Api>(
+    api: &A,
+    tokens: Vec<HumanAddr>,
+    current: &[TokenRewardIndex],
+) -> StdResult<Vec<TokenRewardIndex>> {
+    let mut token_reward_indexes: Vec<TokenRewardIndex> = vec![];
+    for token in tokens {
+        let token = api.canonical_address(&token)?;
+        if token_reward_indexes.iter().any(|r| r.token == token) {
+            return Err(StdError::generic_err("Duplicate reward token"));
+        }
+        token_reward_indexes.push(match current.iter().find(|r| r.token == token) {
+            Some(reward) => reward.clone(),
+            None => TokenRewardIndex {
+                token,
+                index: Uint128::zero(),
+                remainder: Uint128::zero(),
+            },
+        });
+    }
+    Ok(token_reward_indexes)
+}
+
+fn validate_governance(governance: &GovernanceConfig) -> StdResult<()> {
+    if governance.quorum > Decimal::one() || governance.vote_threshold > Decimal::one() {
+        return Err(StdError::generic_err(
+            "Quorum and vote threshold can't exceed 100%",
+        ));
+    }
+    if governance.voting_period == 0 {
+        return Err(StdError::generic_err("Voting period must be at least 1"));
+    }
+    Ok(())
+}
+
+fn validate_lock_tiers(lock_tiers: &[LockTier]) -> StdResult<()> {
+    for tier in lock_tiers.iter() {
+        if tier.duration == 0 || tier.weight < Decimal::one() {
+            return Err(StdError::generic_err(
+                "Lock tier needs a duration and a weight of at least 1",
+            ));
+        }
+    }
+    Ok(())
+}
+
 pub fn handle<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
@@ -38,15 +372,106 @@ pub fn handle<S: Storage, A: Api, Q: Querier>(
 ) -> StdResult<HandleResponse> {
     match msg {
         HandleMsg::Stake { amount } => handle_stake(deps, env, amount),
+        HandleMsg::Receive(msg) => handle_receive(deps, env, msg),
         HandleMsg::UnStake { amount } => handle_unstake(deps, env, amount),
+        HandleMsg::InstantUnStake { amount } => handle_instant_unstake(deps, env, amount),
+        HandleMsg::ReBond { amount } => handle_rebond(deps, env, amount),
+        HandleMsg::Lock { amount, tier } => handle_lock(deps, env, amount, tier),
+        HandleMsg::ExpireLock { address } => handle_expire_lock(deps, env, address),
         HandleMsg::ClaimReward {} => handle_claim_reward(deps, env),
         HandleMsg::ClaimUnStaked {} => handle_claim_unstake(deps, env),
+        HandleMsg::EmergencyWithdraw {} => handle_emergency_withdraw(deps, env),
         HandleMsg::SafeLock {} => handle_safe_lock(deps, env),
-        HandleMsg::Renounce {} => handle_renounce(deps, env),
+        HandleMsg::SetPauseFlags { flags } => handle_set_pause_flags(deps, env, flags),
+        HandleMsg::ExtendPause {} => handle_extend_pause(deps, env),
+        HandleMsg::AcceptAdmin {} => handle_accept_admin(deps, env),
         HandleMsg::PayoutReward {} => handle_payout_reward(deps, env),
+        HandleMsg::PayoutRewardStream {
+            start_height,
+            end_height,
+        } => handle_payout_reward_stream(deps, env, start_height, end_height),
+        // Only removing privileges, applied right away like SafeLock
+        HandleMsg::CancelAdminProposal {} => handle_cancel_admin_proposal(deps, env),
+        HandleMsg::SetGuardian { guardian: None } => handle_set_guardian(deps, env, None),
+        HandleMsg::UpdateConfig { .. }
+        | HandleMsg::SetInstantUnStakePenalty { .. }
+        | HandleMsg::ProposeNewAdmin { .. }
+        | HandleMsg::SetGuardian { .. }
+        | HandleMsg::SetStreamPayers { .. }
+        | HandleMsg::Renounce {}
+        | HandleMsg::RecomputeTotals {} => handle_queue_admin_action(deps, env, msg),
+        HandleMsg::ExecuteAdminAction { id } => handle_execute_admin_action(deps, env, id),
+        HandleMsg::CancelAdminAction { id } => handle_cancel_admin_action(deps, env, id),
+        HandleMsg::CreateProposal { description, msg } => {
+            handle_create_proposal(deps, env, description, msg.map(|msg| *msg))
+        }
+        HandleMsg::CastVote {
+            proposal_id,
+            option,
+        } => handle_cast_vote(deps, env, proposal_id, option),
+        HandleMsg::EndProposal { proposal_id } => handle_end_proposal(deps, env, proposal_id),
+        HandleMsg::ExecuteProposal { proposal_id } => {
+            handle_execute_proposal(deps, env, proposal_id)
+        }
+    }
+}
+
+/// Apply an admin action, the sender is checked by each handler
+fn apply_admin_action<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    msg: HandleMsg,
+) -> StdResult<HandleResponse> {
+    match msg {
+        HandleMsg::Renounce {} => handle_renounce(deps, env),
+        HandleMsg::ProposeNewAdmin { address, expiry } => {
+            handle_propose_new_admin(deps, env, address, expiry)
+        }
+        HandleMsg::CancelAdminProposal {} => handle_cancel_admin_proposal(deps, env),
+        HandleMsg::SetGuardian { guardian } => handle_set_guardian(deps, env, guardian),
+        HandleMsg::SetStreamPayers { payers } => handle_set_stream_payers(deps, env, payers),
+        HandleMsg::SetInstantUnStakePenalty {
+            rate,
+            destination,
+            treasury,
+        } => handle_set_instant_unstake_penalty(deps, env, rate, destination, treasury),
+        HandleMsg::UpdateConfig {
+            unbonded_period,
+            reward_denoms,
+            reward_tokens,
+            address_cw20_loterra_smart_contract,
+            max_unbonding_entries,
+            carry_reward_remainder,
+            lock_tiers,
+            min_bonding_age,
+            timelock_delay,
+            emergency_withdraw_delay,
+            max_pause_duration,
+            governance,
+        } => handle_update_config(
+            deps,
+            env,
+            ConfigUpdate {
+                unbonded_period,
+                reward_denoms,
+                reward_tokens,
+                address_cw20_loterra_smart_contract,
+                max_unbonding_entries,
+                carry_reward_remainder,
+                lock_tiers,
+                min_bonding_age,
+                timelock_delay,
+                emergency_withdraw_delay,
+                max_pause_duration,
+                governance: governance.map(|governance| *governance),
+            },
+        ),
+        HandleMsg::RecomputeTotals {} => handle_recompute_totals(deps, env),
+        _ => Err(StdError::generic_err("Not an admin action")),
     }
 }
-fn encode_msg_execute(msg: QueryMsg, address: HumanAddr) -> StdResult<CosmosMsg> {
+
+fn encode_msg_execute(msg: Cw20ExecuteMsg, address: HumanAddr) -> StdResult<CosmosMsg> {
     Ok(WasmMsg::Execute {
         contract_addr: address,
         msg: to_binary(&msg)?,
@@ -55,674 +480,6332 @@ fn encode_msg_execute(msg: QueryMsg, address: HumanAddr) -> StdResult<CosmosMsg>
     .into())
 }
 
-pub fn handle_renounce<S: Storage, A: Api, Q: Querier>(
-    deps: &mut Extern<S, A, Q>,
-    env: Env,
-) -> StdResult<HandleResponse> {
-    // Load the state
-    let mut state = config(&mut deps.storage).load()?;
-    let sender = deps.api.canonical_address(&env.message.sender)?;
-    if state.admin != sender {
-        return Err(StdError::Unauthorized { backtrace: None });
+/// Add amount of denom to coins, zero amounts are not stored
+fn add_coin(coins: &mut Vec<Coin>, denom: &str, amount: Uint128) {
+    if amount.is_zero() {
+        return;
     }
-    if state.safe_lock {
-        return Err(StdError::generic_err("Contract is locked"));
+    match coins.iter_mut().find(|coin| coin.denom == denom) {
+        Some(coin) => coin.amount = coin.amount.add(amount),
+        None => coins.push(Coin {
+            denom: denom.to_string(),
+            amount,
+        }),
     }
-
-    state.admin = deps.api.canonical_address(&env.contract.address)?;
-    config(&mut deps.storage).save(&state)?;
-    Ok(HandleResponse::default())
 }
 
-pub fn handle_safe_lock<S: Storage, A: Api, Q: Querier>(
-    deps: &mut Extern<S, A, Q>,
-    env: Env,
-) -> StdResult<HandleResponse> {
-    // Load the state
-    let mut state = config(&mut deps.storage).load()?;
-    let sender = deps.api.canonical_address(&env.message.sender)?;
-    if state.admin != sender {
-        return Err(StdError::Unauthorized { backtrace: None });
-    }
-
-    state.safe_lock = !state.safe_lock;
-    config(&mut deps.storage).save(&state)?;
-
-    Ok(HandleResponse::default())
+fn coins_to_string(coins: &[Coin]) -> String {
+    coins
+        .iter()
+        .map(|coin| format!("{}{}", coin.amount, coin.denom))
+        .collect::<Vec<String>>()
+        .join(",")
 }
 
-pub fn handle_stake<S: Storage, A: Api, Q: Querier>(
-    deps: &mut Extern<S, A, Q>,
-    env: Env,
+/// Spread amount over total weighted by raising the index, returns whole units left over
+fn distribute_reward(
+    total_weighted: Uint128,
     amount: Uint128,
-) -> StdResult<HandleResponse> {
-    let state = config(&mut deps.storage).load()?;
+    index: &mut Uint128,
+    remainder: &mut Uint128,
+) -> Uint128 {
+    let scaled = amount.u128() * REWARD_INDEX_SCALE + remainder.u128();
+    let left = scaled % total_weighted.u128();
+    *index = index.add(Uint128(scaled / total_weighted.u128()));
+    *remainder = Uint128(left % REWARD_INDEX_SCALE);
+    // Whole units the index precision can't represent
+    Uint128(left / REWARD_INDEX_SCALE)
+}
 
-    if state.safe_lock {
-        return Err(StdError::generic_err(
-            "Contract deactivated for update or/and preventing security issue",
-        ));
+/// Distribute the part of every reward stream released up to height
+fn release_streams(state: &mut State, height: u64) {
+    // Without stakers the released part waits for the first ones
+    if state.total_weighted.is_zero() {
+        return;
+    }
+    let total_weighted = state.total_weighted;
+    let reward_indexes = &mut state.reward_indexes;
+    for stream in state.reward_streams.iter_mut() {
+        if height <= stream.start_height {
+            continue;
+        }
+        let elapsed = height.min(stream.end_height) - stream.start_height;
+        let target = stream
+            .amount
+            .multiply_ratio(elapsed, stream.end_height - stream.start_height);
+        let delta = Uint128(target.u128() - stream.released.u128());
+        if delta.is_zero() {
+            continue;
+        }
+        if let Some(reward) = reward_indexes.iter_mut().find(|r| r.denom == stream.denom) {
+            let dust = distribute_reward(
+                total_weighted,
+                delta,
+                &mut reward.index,
+                &mut reward.remainder,
+            );
+            // No payer to refund, stream dust waits for the next distribution
+            reward.remainder = reward
+                .remainder
+                .add(Uint128(dust.u128() * REWARD_INDEX_SCALE));
+            stream.released = target;
+        }
     }
+    state
+        .reward_streams
+        .retain(|stream| stream.released < stream.amount);
+}
 
-    if !env.message.sent_funds.is_empty() {
-        return Err(StdError::generic_err("Do not send funds with stake"));
+/// Check every coin is a whitelisted reward denom
+fn check_reward_denoms(state: &State, funds: &[Coin]) -> StdResult<()> {
+    for coin in funds.iter() {
+        if !state.reward_indexes.iter().any(|r| r.denom == coin.denom) {
+            let denoms: Vec<String> = state
+                .reward_indexes
+                .iter()
+                .map(|r| r.denom.clone())
+                .collect();
+            return Err(StdError::generic_err(format!(
+                "Only {} is accepted",
+                denoms.join(", ")
+            )));
+        }
     }
-    if amount.is_zero(){
-        return Err(StdError::generic_err("Amount required"));
+    Ok(())
+}
+
+/// Bonded amount with the locked part counted at its tier weight
+fn weighted_bonded(stake: &StakingInfo) -> Uint128 {
+    match &stake.lock {
+        Some(lock) => {
+            Uint128(stake.bonded.u128() - lock.amount.u128() - warming_amount(stake).u128())
+                .add(lock.amount * lock.weight)
+        }
+        None => Uint128(stake.bonded.u128() - warming_amount(stake).u128()),
     }
-    // Prepare msg to send
-    let msg = QueryMsg::TransferFrom {
-        owner: env.message.sender.clone(),
-        recipient: env.contract.address.clone(),
-        amount,
-    };
-    // Convert state address of loterra cw-20
-    let lottera_human = deps
-        .api
-        .human_address(&state.address_cw20_loterra_smart_contract.clone())?;
-    // Prepare the message
-    let res = encode_msg_execute(msg, lottera_human)?;
+}
 
-    let sender_canonical = deps.api.canonical_address(&env.message.sender)?;
-    match staking_storage(&mut deps.storage).may_load(&sender_canonical.as_slice())? {
-        Some(_e) => {
-            staking_storage(&mut deps.storage).update::<_>(
-                &sender_canonical.as_slice(),
-                |stake| {
-                    let mut stake_data = stake.unwrap();
-                    stake_data.bonded = stake_data.bonded.add(amount);
-
-                    Ok(stake_data)
+/// Part of bonded still warming up
+fn warming_amount(stake: &StakingInfo) -> Uint128 {
+    stake
+        .warming
+        .iter()
+        .fold(Uint128::zero(), |total, entry| total.add(entry.amount))
+}
+
+/// Bring the distribution up to height, streams release up to the height of each warming
+/// batch or lock batch before the weight changes
+fn advance<S: Storage>(storage: &mut S, state: &mut State, height: u64) -> StdResult<()> {
+    let end = (height + 1).to_be_bytes();
+    let matured = warming_storage_read(storage)
+        .range(None, Some(&end), Order::Ascending)
+        .collect::<StdResult<Vec<(Vec<u8>, WarmingBatch)>>>()?;
+    let expired = lock_expiry_storage_read(storage)
+        .range(None, Some(&end), Order::Ascending)
+        .collect::<StdResult<Vec<(Vec<u8>, LockExpiryBatch)>>>()?;
+    // Big endian keys sort by height
+    let mut keys: Vec<Vec<u8>> = matured
+        .iter()
+        .map(|(key, _)| key.clone())
+        .chain(expired.iter().map(|(key, _)| key.clone()))
+        .collect();
+    keys.sort();
+    keys.dedup();
+    for key in keys {
+        let mut batch_height = [0u8; 8];
+        batch_height.copy_from_slice(&key);
+        release_streams(state, u64::from_be_bytes(batch_height));
+        if let Some((_, batch)) = matured.iter().find(|(matured_key, _)| *matured_key == key) {
+            warming_storage(storage).remove(&key);
+            state.total_warming = state.total_warming.sub(batch.amount)?;
+            state.total_weighted = state.total_weighted.add(batch.amount);
+            // Holders of the batch accrue from these indexes when they settle
+            matured_storage(storage).save(
+                &key,
+                &MaturedBatch {
+                    holders: batch.holders,
+                    reward_indexes: state.reward_indexes.clone(),
+                    token_reward_indexes: state.token_reward_indexes.clone(),
                 },
             )?;
         }
-        None => {
-            staking_storage(&mut deps.storage).save(
-                &sender_canonical.as_slice(),
-                &StakingInfo {
-                    bonded: amount,
-                    un_bonded: Uint128::zero(),
-                    period: 0,
-                    available: Uint128::zero(),
+        if let Some((_, batch)) = expired.iter().find(|(expired_key, _)| *expired_key == key) {
+            lock_expiry_storage(storage).remove(&key);
+            state.total_weighted = state.total_weighted.sub(batch.weighted)?.add(batch.amount);
+            // Holders of the batch accrue at their tier weight up to these indexes
+            expired_lock_storage(storage).save(
+                &key,
+                &MaturedBatch {
+                    holders: batch.holders,
+                    reward_indexes: state.reward_indexes.clone(),
+                    token_reward_indexes: state.token_reward_indexes.clone(),
                 },
             )?;
         }
+    }
+    release_streams(state, height);
+    Ok(())
+}
+
+/// Add a deposit to the holder and global warming batches
+fn warm_up<S: Storage>(
+    storage: &mut S,
+    state: &mut State,
+    stake: &mut StakingInfo,
+    amount: Uint128,
+    height: u64,
+) -> StdResult<()> {
+    let eligible_height = height + state.min_bonding_age;
+    let key = eligible_height.to_be_bytes();
+    let mut batch = warming_storage_read(storage)
+        .may_load(&key)?
+        .unwrap_or(WarmingBatch {
+            amount: Uint128::zero(),
+            holders: 0,
+        });
+    match stake
+        .warming
+        .iter_mut()
+        .find(|entry| entry.eligible_height == eligible_height)
+    {
+        Some(entry) => entry.amount = entry.amount.add(amount),
+        None => {
+            if stake.warming.len() >= MAX_WARMING_ENTRIES {
+                return Err(StdError::generic_err(format!(
+                    "Too many warming up deposits (max {})",
+                    MAX_WARMING_ENTRIES
+                )));
+            }
+            stake.warming.push(WarmingInfo {
+                amount,
+                eligible_height,
+            });
+            batch.holders += 1;
+        }
+    }
+    batch.amount = batch.amount.add(amount);
+    warming_storage(storage).save(&key, &batch)?;
+    state.total_warming = state.total_warming.add(amount);
+    Ok(())
+}
+
+/// Remove up to amount from the newest warming deposits, returns the amount removed
+fn cool_down<S: Storage>(
+    storage: &mut S,
+    state: &mut State,
+    stake: &mut StakingInfo,
+    amount: Uint128,
+) -> StdResult<Uint128> {
+    let mut removed = Uint128::zero();
+    while removed < amount {
+        let entry = match stake.warming.last_mut() {
+            Some(entry) => entry,
+            None => break,
+        };
+        let key = entry.eligible_height.to_be_bytes();
+        let taken = entry.amount.min(Uint128(amount.u128() - removed.u128()));
+        entry.amount = entry.amount.sub(taken)?;
+        let mut batch = warming_storage_read(storage).load(&key)?;
+        batch.amount = batch.amount.sub(taken)?;
+        if entry.amount.is_zero() {
+            stake.warming.pop();
+            batch.holders -= 1;
+        }
+        if batch.holders == 0 {
+            warming_storage(storage).remove(&key);
+        } else {
+            warming_storage(storage).save(&key, &batch)?;
+        }
+        removed = removed.add(taken);
+    }
+    state.total_warming = state.total_warming.sub(removed)?;
+    Ok(removed)
+}
+
+/// Add the lock of a holder to the batch expiring with it
+fn add_lock_expiry<S: Storage>(storage: &mut S, lock: &LockInfo) -> StdResult<()> {
+    let key = lock.expiry.to_be_bytes();
+    let mut batch = lock_expiry_storage_read(storage)
+        .may_load(&key)?
+        .unwrap_or(LockExpiryBatch {
+            amount: Uint128::zero(),
+            weighted: Uint128::zero(),
+            holders: 0,
+        });
+    batch.amount = batch.amount.add(lock.amount);
+    batch.weighted = batch.weighted.add(lock.amount * lock.weight);
+    batch.holders += 1;
+    lock_expiry_storage(storage).save(&key, &batch)
+}
+
+/// Remove the lock of a holder from the batch expiring with it
+fn remove_lock_expiry<S: Storage>(storage: &mut S, lock: &LockInfo) -> StdResult<()> {
+    let key = lock.expiry.to_be_bytes();
+    let mut batch = lock_expiry_storage_read(storage).load(&key)?;
+    batch.amount = batch.amount.sub(lock.amount)?;
+    batch.weighted = batch.weighted.sub(lock.amount * lock.weight)?;
+    batch.holders -= 1;
+    if batch.holders == 0 {
+        lock_expiry_storage(storage).remove(&key);
+    } else {
+        lock_expiry_storage(storage).save(&key, &batch)?;
+    }
+    Ok(())
+}
+
+/// Accrue the weighted share of the index growth since the checkpoint, returns whole units
+fn accrue_reward(
+    weighted: Uint128,
+    global_index: Uint128,
+    checkpoint_index: &mut Uint128,
+    remainder: &mut Uint128,
+) -> Uint128 {
+    let index_diff = global_index.u128() - checkpoint_index.u128();
+    let accrued = weighted.u128() * index_diff + remainder.u128();
+    *checkpoint_index = global_index;
+    *remainder = Uint128(accrued % REWARD_INDEX_SCALE);
+    Uint128(accrued / REWARD_INDEX_SCALE)
+}
+
+/// Add amount to the balance of token
+fn add_token_balance(balances: &mut Vec<TokenBalance>, token: &CanonicalAddr, amount: Uint128) {
+    if amount.is_zero() {
+        return;
+    }
+    match balances.iter_mut().find(|balance| balance.token == *token) {
+        Some(balance) => balance.amount = balance.amount.add(amount),
+        None => balances.push(TokenBalance {
+            token: token.clone(),
+            amount,
+        }),
+    }
+}
+
+/// Move the rewards accrued since the holder last checkpoint up to the given indexes into available
+fn settle_reward(
+    reward_indexes: &[RewardIndex],
+    token_reward_indexes: &[TokenRewardIndex],
+    stake: &mut StakingInfo,
+) {
+    let weighted = weighted_bonded(stake);
+    for global in reward_indexes.iter() {
+        // Denoms without checkpoint start from zero, as their global index did
+        let position = match stake
+            .reward_indexes
+            .iter()
+            .position(|checkpoint| checkpoint.denom == global.denom)
+        {
+            Some(position) => position,
+            None => {
+                stake.reward_indexes.push(RewardIndex {
+                    denom: global.denom.clone(),
+                    index: Uint128::zero(),
+                    remainder: Uint128::zero(),
+                });
+                stake.reward_indexes.len() - 1
+            }
+        };
+        let checkpoint = &mut stake.reward_indexes[position];
+        let reward = accrue_reward(
+            weighted,
+            global.index,
+            &mut checkpoint.index,
+            &mut checkpoint.remainder,
+        );
+        add_coin(&mut stake.available, &global.denom, reward);
+    }
+
+    for global in token_reward_indexes.iter() {
+        let position = match stake
+            .token_reward_indexes
+            .iter()
+            .position(|checkpoint| checkpoint.token == global.token)
+        {
+            Some(position) => position,
+            None => {
+                stake.token_reward_indexes.push(TokenRewardIndex {
+                    token: global.token.clone(),
+                    index: Uint128::zero(),
+                    remainder: Uint128::zero(),
+                });
+                stake.token_reward_indexes.len() - 1
+            }
+        };
+        let checkpoint = &mut stake.token_reward_indexes[position];
+        let reward = accrue_reward(
+            weighted,
+            global.index,
+            &mut checkpoint.index,
+            &mut checkpoint.remainder,
+        );
+        add_token_balance(&mut stake.available_tokens, &global.token, reward);
+    }
+}
+
+/// Settle the holder, dropping its lock at the indexes of its expiry once processed, then
+/// credit its deposits of matured batches from the indexes at maturity, returns the eligible
+/// heights of those deposits
+fn settle_matured<S: Storage>(
+    storage: &S,
+    state: &State,
+    stake: &mut StakingInfo,
+) -> StdResult<Vec<u64>> {
+    if let Some(lock) = &stake.lock {
+        if let Some(batch) =
+            expired_lock_storage_read(storage).may_load(&lock.expiry.to_be_bytes())?
+        {
+            settle_reward(&batch.reward_indexes, &batch.token_reward_indexes, stake);
+            stake.lock = None;
+        }
+    }
+    settle_reward(&state.reward_indexes, &state.token_reward_indexes, stake);
+    let mut matured = vec![];
+    let mut warming = vec![];
+    for entry in std::mem::take(&mut stake.warming) {
+        let key = entry.eligible_height.to_be_bytes();
+        let batch = match matured_storage_read(storage).may_load(&key)? {
+            Some(batch) => batch,
+            None => {
+                warming.push(entry);
+                continue;
+            }
+        };
+        // Checkpoints of every global index exist once settled
+        for global in state.reward_indexes.iter() {
+            let checkpoint = match stake
+                .reward_indexes
+                .iter_mut()
+                .find(|checkpoint| checkpoint.denom == global.denom)
+            {
+                Some(checkpoint) => checkpoint,
+                None => continue,
+            };
+            let mut index = batch
+                .reward_indexes
+                .iter()
+                .find(|r| r.denom == global.denom)
+                .map(|r| r.index)
+                .unwrap_or_else(Uint128::zero);
+            let reward = accrue_reward(
+                entry.amount,
+                global.index,
+                &mut index,
+                &mut checkpoint.remainder,
+            );
+            add_coin(&mut stake.available, &global.denom, reward);
+        }
+        for global in state.token_reward_indexes.iter() {
+            let checkpoint = match stake
+                .token_reward_indexes
+                .iter_mut()
+                .find(|checkpoint| checkpoint.token == global.token)
+            {
+                Some(checkpoint) => checkpoint,
+                None => continue,
+            };
+            let mut index = batch
+                .token_reward_indexes
+                .iter()
+                .find(|r| r.token == global.token)
+                .map(|r| r.index)
+                .unwrap_or_else(Uint128::zero);
+            let reward = accrue_reward(
+                entry.amount,
+                global.index,
+                &mut index,
+                &mut checkpoint.remainder,
+            );
+            add_token_balance(&mut stake.available_tokens, &global.token, reward);
+        }
+        matured.push(entry.eligible_height);
+    }
+    stake.warming = warming;
+    Ok(matured)
+}
+
+/// Settle the holder rewards and release its share of the matured and expired batches
+fn settle<S: Storage>(storage: &mut S, state: &State, stake: &mut StakingInfo) -> StdResult<()> {
+    let expiry = stake.lock.as_ref().map(|lock| lock.expiry);
+    for height in settle_matured(storage, state, stake)? {
+        let key = height.to_be_bytes();
+        let mut batch = matured_storage_read(storage).load(&key)?;
+        batch.holders -= 1;
+        if batch.holders == 0 {
+            matured_storage(storage).remove(&key);
+        } else {
+            matured_storage(storage).save(&key, &batch)?;
+        }
+    }
+    // The lock is only dropped once its batch expired
+    if let (Some(expiry), None) = (expiry, &stake.lock) {
+        let key = expiry.to_be_bytes();
+        let mut batch = expired_lock_storage_read(storage).load(&key)?;
+        batch.holders -= 1;
+        if batch.holders == 0 {
+            expired_lock_storage(storage).remove(&key);
+        } else {
+            expired_lock_storage(storage).save(&key, &batch)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn handle_queue_admin_action<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    msg: HandleMsg,
+) -> StdResult<HandleResponse> {
+    // Load the state
+    let mut state = config(&mut deps.storage).load()?;
+    let sender = deps.api.canonical_address(&env.message.sender)?;
+    if state.admin != sender {
+        return Err(StdError::Unauthorized { backtrace: None });
+    }
+    if state.timelock_delay == 0 {
+        return apply_admin_action(deps, env, msg);
+    }
+
+    let action = AdminAction {
+        id: state.next_admin_action_id,
+        msg,
+        execute_after: env.block.height + state.timelock_delay,
     };
+    admin_action_storage(&mut deps.storage).save(&action.id.to_be_bytes(), &action)?;
+    state.next_admin_action_id += 1;
+    config(&mut deps.storage).save(&state)?;
 
     Ok(HandleResponse {
-        messages: vec![res.into()],
+        messages: vec![],
         log: vec![
             LogAttribute {
                 key: "action".to_string(),
-                value: "bond lota".to_string(),
-            },
-            LogAttribute {
-                key: "from".to_string(),
-                value: env.message.sender.to_string(),
+                value: "queue admin action".to_string(),
             },
             LogAttribute {
-                key: "to".to_string(),
-                value: env.contract.address.to_string(),
+                key: "id".to_string(),
+                value: action.id.to_string(),
             },
             LogAttribute {
-                key: "amount".to_string(),
-                value: amount.to_string(),
+                key: "execute_after".to_string(),
+                value: action.execute_after.to_string(),
             },
         ],
         data: None,
     })
 }
 
-pub fn handle_unstake<S: Storage, A: Api, Q: Querier>(
+pub fn handle_execute_admin_action<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
-    amount: Uint128,
+    id: u64,
 ) -> StdResult<HandleResponse> {
     let state = config(&mut deps.storage).load()?;
-
-    if state.safe_lock {
-        return Err(StdError::generic_err(
-            "Contract deactivated for update or/and preventing security issue",
-        ));
+    let key = id.to_be_bytes();
+    let action = match admin_action_storage_read(&deps.storage).may_load(&key)? {
+        Some(action) => action,
+        None => {
+            return Err(StdError::generic_err(format!(
+                "Admin action {} not found",
+                id
+            )))
+        }
+    };
+    if env.block.height < action.execute_after {
+        return Err(StdError::generic_err(format!(
+            "Admin action {} can be executed from block {}",
+            id, action.execute_after
+        )));
     }
+    admin_action_storage(&mut deps.storage).remove(&key);
 
-    if !env.message.sent_funds.is_empty() {
-        return Err(StdError::generic_err("Do not send funds with un_stake"));
+    // Queued actions are dropped on an admin change, so the current admin queued this one
+    let mut admin_env = env;
+    admin_env.message.sender = deps.api.human_address(&state.admin)?;
+    let mut res = apply_admin_action(deps, admin_env, action.msg)?;
+    res.log.insert(
+        0,
+        LogAttribute {
+            key: "admin_action".to_string(),
+            value: id.to_string(),
+        },
+    );
+    Ok(res)
+}
+
+pub fn handle_create_proposal<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    description: String,
+    msg: Option<HandleMsg>,
+) -> StdResult<HandleResponse> {
+    let mut state = config(&mut deps.storage).load()?;
+    if description.is_empty() || description.len() > MAX_DESCRIPTION_LENGTH {
+        return Err(StdError::generic_err(format!(
+            "Description must be between 1 and {} characters",
+            MAX_DESCRIPTION_LENGTH
+        )));
     }
-    if amount.is_zero(){
-        return Err(StdError::generic_err("Amount required"));
+    match &msg {
+        None | Some(HandleMsg::UpdateConfig { .. }) => {}
+        Some(_) => {
+            return Err(StdError::generic_err(
+                "Proposals can only change the config",
+            ))
+        }
+    }
+    let proposer = deps.api.canonical_address(&env.message.sender)?;
+    let bonded = match staking_storage_read(&deps.storage).may_load(proposer.as_slice())? {
+        Some(stake) => stake.bonded,
+        None => Uint128::zero(),
+    };
+    if bonded.is_zero() || bonded < state.governance.proposal_min_stake {
+        return Err(StdError::generic_err(format!(
+            "You need {} bonded to create a proposal",
+            state.governance.proposal_min_stake
+        )));
     }
 
-    let sender_canonical = deps.api.canonical_address(&env.message.sender)?;
-    match staking_storage(&mut deps.storage).may_load(&sender_canonical.as_slice())? {
-        Some(_e) => {
-            staking_storage(&mut deps.storage).update::<_>(
-                &sender_canonical.as_slice(),
-                |stake| {
-                    let mut stake_data = stake.unwrap();
-                    if stake_data.bonded < amount {
-                        return Err(StdError::generic_err(format!(
-                            "You can't unStake more than you have ({})",
-                            stake_data.bonded.u128().to_string()
-                        )));
-                    }
-                    stake_data.bonded = stake_data.bonded.sub(amount)?;
-                    stake_data.un_bonded = stake_data.un_bonded.add(amount);
-                    stake_data.period = env.block.height + state.unbonded_period;
-                    Ok(stake_data)
-                },
-            )?;
-        }
-        None => {
-            return Err(StdError::Unauthorized { backtrace: None });
-        }
+    let proposal = Proposal {
+        id: state.next_proposal_id,
+        proposer,
+        description,
+        msg,
+        start_height: env.block.height,
+        end_height: env.block.height + state.governance.voting_period,
+        total_power: Uint128::zero(),
+        yes: Uint128::zero(),
+        no: Uint128::zero(),
+        status: ProposalStatus::Open,
     };
+    proposal_storage(&mut deps.storage).save(&proposal.id.to_be_bytes(), &proposal)?;
+    state.next_proposal_id += 1;
+    config(&mut deps.storage).save(&state)?;
 
     Ok(HandleResponse {
         messages: vec![],
         log: vec![
             LogAttribute {
                 key: "action".to_string(),
-                value: "unbond lota".to_string(),
+                value: "create proposal".to_string(),
             },
             LogAttribute {
-                key: "amount".to_string(),
-                value: amount.to_string(),
+                key: "proposal_id".to_string(),
+                value: proposal.id.to_string(),
+            },
+            LogAttribute {
+                key: "end_height".to_string(),
+                value: proposal.end_height.to_string(),
             },
         ],
         data: None,
     })
 }
 
-pub fn handle_claim_unstake<S: Storage, A: Api, Q: Querier>(
+fn load_proposal<S: Storage>(storage: &S, proposal_id: u64) -> StdResult<Proposal> {
+    match proposal_storage_read(storage).may_load(&proposal_id.to_be_bytes())? {
+        Some(proposal) => Ok(proposal),
+        None => Err(StdError::generic_err(format!(
+            "Proposal {} not found",
+            proposal_id
+        ))),
+    }
+}
+
+pub fn handle_cast_vote<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
+    proposal_id: u64,
+    option: VoteOption,
 ) -> StdResult<HandleResponse> {
-    let state = config(&mut deps.storage).load()?;
-
-    if state.safe_lock {
-        return Err(StdError::generic_err(
-            "Contract deactivated for update or/and preventing security issue",
-        ));
-    }
-
-    if !env.message.sent_funds.is_empty() {
-        return Err(StdError::generic_err("Do not send funds"));
+    let mut proposal = load_proposal(&deps.storage, proposal_id)?;
+    // The start height checkpoint is final from the next block
+    if env.block.height <= proposal.start_height {
+        return Err(StdError::generic_err(format!(
+            "Voting starts at block {}",
+            proposal.start_height + 1
+        )));
     }
-
-    let sender_canonical = deps.api.canonical_address(&env.message.sender)?;
-    let store = staking_storage(&mut deps.storage).load(&sender_canonical.as_slice())?;
-
-    if store.period > env.block.height {
+    if env.block.height > proposal.end_height {
         return Err(StdError::generic_err(format!(
-            "Your unBonded token will be released at block {}",
-            store.period
+            "Voting ended at block {}",
+            proposal.end_height
         )));
     }
-    if store.un_bonded.is_zero(){
-        return Err(StdError::generic_err("No amount available"))
+    let voter = deps.api.canonical_address(&env.message.sender)?;
+    if vote_storage_read(&deps.storage, proposal_id)
+        .may_load(voter.as_slice())?
+        .is_some()
+    {
+        return Err(StdError::generic_err("Already voted"));
+    }
+    // Tokens moved or unStaked after the start height carry no power here, nobody votes twice
+    let power = power_at(
+        voting_power_storage_read(&deps.storage, &voter),
+        proposal.start_height,
+    )?;
+    if power.is_zero() {
+        return Err(StdError::generic_err(format!(
+            "No voting power at block {}",
+            proposal.start_height
+        )));
     }
-    // Prepare msg to send
-    let msg = QueryMsg::Transfer {
-        recipient: env.message.sender.clone(),
-        amount: store.un_bonded,
-    };
-    // Convert state address of loterra cw-20
-    let lottera_human = deps
-        .api
-        .human_address(&state.address_cw20_loterra_smart_contract.clone())?;
-    // Prepare the message
-    let res = encode_msg_execute(msg, lottera_human)?;
 
-    staking_storage(&mut deps.storage).update::<_>(&sender_canonical.as_slice(), |stake| {
-        let mut stake_data = stake.unwrap();
-        stake_data.un_bonded = Uint128::zero();
-        stake_data.period = 0;
-        Ok(stake_data)
-    })?;
+    match option {
+        VoteOption::Yes => proposal.yes = proposal.yes.add(power),
+        VoteOption::No => proposal.no = proposal.no.add(power),
+    }
+    vote_storage(&mut deps.storage, proposal_id).save(
+        voter.as_slice(),
+        &Vote {
+            option: option.clone(),
+            power,
+        },
+    )?;
+    proposal_storage(&mut deps.storage).save(&proposal_id.to_be_bytes(), &proposal)?;
 
     Ok(HandleResponse {
-        messages: vec![res.into()],
+        messages: vec![],
         log: vec![
             LogAttribute {
                 key: "action".to_string(),
-                value: "claim unstake".to_string(),
+                value: "cast vote".to_string(),
             },
             LogAttribute {
-                key: "from".to_string(),
-                value: env.contract.address.to_string(),
+                key: "proposal_id".to_string(),
+                value: proposal_id.to_string(),
             },
             LogAttribute {
-                key: "to".to_string(),
-                value: env.message.sender.to_string(),
+                key: "option".to_string(),
+                value: format!("{:?}", option).to_lowercase(),
             },
             LogAttribute {
-                key: "amount".to_string(),
-                value: store.un_bonded.to_string(),
+                key: "power".to_string(),
+                value: power.to_string(),
             },
         ],
         data: None,
     })
 }
 
-pub fn handle_claim_reward<S: Storage, A: Api, Q: Querier>(
+pub fn handle_end_proposal<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
+    proposal_id: u64,
 ) -> StdResult<HandleResponse> {
     let state = config(&mut deps.storage).load()?;
-
-    if state.safe_lock {
-        return Err(StdError::generic_err(
-            "Contract deactivated for update or/and preventing security issue",
-        ));
-    }
-
-    if !env.message.sent_funds.is_empty() {
-        return Err(StdError::generic_err("Do not send funds"));
-    }
-
-    let sender_canonical = deps.api.canonical_address(&env.message.sender)?;
-    let store = staking_storage(&mut deps.storage).load(&sender_canonical.as_slice())?;
-    let contract_balance = deps
-        .querier
-        .query_balance(env.contract.address.clone(), &state.denom_reward)?;
-
-    if store.available.is_zero() {
-        return Err(StdError::generic_err("No rewards available"));
+    let mut proposal = load_proposal(&deps.storage, proposal_id)?;
+    if proposal.status != ProposalStatus::Open {
+        return Err(StdError::generic_err(format!(
+            "Proposal {} already ended",
+            proposal_id
+        )));
     }
-
-    if contract_balance.amount < store.available {
-        return Err(StdError::generic_err("Contract balance too low"));
+    if env.block.height <= proposal.end_height {
+        return Err(StdError::generic_err(format!(
+            "Voting ends at block {}",
+            proposal.end_height
+        )));
     }
 
-    let msg = BankMsg::Send {
-        from_address: env.contract.address.clone(),
-        to_address: env.message.sender.clone(),
-        amount: vec![Coin {
-            denom: state.denom_reward,
-            amount: store.available,
-        }],
+    proposal.total_power = power_at(
+        total_voting_power_storage_read(&deps.storage),
+        proposal.start_height,
+    )?;
+    let votes = proposal.yes.add(proposal.no);
+    let quorum_reached =
+        !votes.is_zero() && votes >= proposal.total_power * state.governance.quorum;
+    let threshold_reached = proposal.yes > votes * state.governance.vote_threshold;
+    proposal.status = if quorum_reached && threshold_reached {
+        ProposalStatus::Passed
+    } else {
+        ProposalStatus::Rejected
     };
-
-    staking_storage(&mut deps.storage).update::<_>(&sender_canonical.as_slice(), |stake| {
-        let mut stake_data = stake.unwrap();
-        stake_data.available = Uint128::zero();
-        Ok(stake_data)
-    })?;
+    proposal_storage(&mut deps.storage).save(&proposal_id.to_be_bytes(), &proposal)?;
 
     Ok(HandleResponse {
-        messages: vec![msg.into()],
+        messages: vec![],
         log: vec![
             LogAttribute {
                 key: "action".to_string(),
-                value: "claim reward".to_string(),
+                value: "end proposal".to_string(),
             },
             LogAttribute {
-                key: "from".to_string(),
-                value: env.contract.address.to_string(),
-            },
-            LogAttribute {
-                key: "to".to_string(),
-                value: env.message.sender.to_string(),
+                key: "proposal_id".to_string(),
+                value: proposal_id.to_string(),
             },
             LogAttribute {
-                key: "amount".to_string(),
-                value: store.available.to_string(),
+                key: "status".to_string(),
+                value: format!("{:?}", proposal.status).to_lowercase(),
             },
         ],
         data: None,
     })
 }
 
-pub fn handle_payout_reward<S: Storage, A: Api, Q: Querier>(
+pub fn handle_execute_proposal<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
+    proposal_id: u64,
 ) -> StdResult<HandleResponse> {
     let state = config(&mut deps.storage).load()?;
-    if state.safe_lock {
-        return Err(StdError::generic_err(
-            "Contract deactivated for update or/and preventing security issue",
-        ));
+    let mut proposal = load_proposal(&deps.storage, proposal_id)?;
+    if proposal.status != ProposalStatus::Passed {
+        return Err(StdError::generic_err(format!(
+            "Proposal {} has not passed",
+            proposal_id
+        )));
     }
-
-    let sent = match env.message.sent_funds.len() {
-        0 => Err(StdError::generic_err(format!(
-            "You need to send funds for share holders"
-        ))),
-        1 => {
-            if env.message.sent_funds[0].denom == state.denom_reward {
-                Ok(env.message.sent_funds[0].amount)
-            } else {
-                Err(StdError::generic_err(format!(
-                    "Only {} is accepted",
-                    state.denom_reward.clone()
-                )))
-            }
+    let msg = match proposal.msg.clone() {
+        Some(msg) => msg,
+        None => {
+            return Err(StdError::generic_err(format!(
+                "Proposal {} has nothing to execute",
+                proposal_id
+            )))
         }
-        _ => Err(StdError::generic_err(format!(
-            "Send only {}, extra denom detected",
-            state.denom_reward.clone()
-        ))),
-    }?;
-
-    let mut total_staked = Uint128::zero();
-    let staking = staking_storage(&mut deps.storage)
-        .range(None, None, Order::Descending)
-        .flat_map(|item| {
-            item.and_then(|(k, stake)| {
-                if !stake.bonded.is_zero() {
-                    total_staked = total_staked.add(stake.bonded);
-                }
-
-                Ok(GetBondedResponse {
-                    address: CanonicalAddr::from(k),
-                    bonded: stake.bonded,
-                })
-            })
-        })
-        .collect::<Vec<GetBondedResponse>>();
-
-    if total_staked.is_zero() {
-        //return Err(StdError::generic_err("No amount staked"));
-        let msg_no_stakers = BankMsg::Send {
-            from_address: env.contract.address.clone(),
-            to_address: env.message.sender.clone(),
-            amount: vec![Coin {
-                denom: state.denom_reward,
-                amount: sent,
-            }],
-        };
+    };
+    proposal.status = ProposalStatus::Executed;
+    proposal_storage(&mut deps.storage).save(&proposal_id.to_be_bytes(), &proposal)?;
 
-        return Ok(HandleResponse {
-            messages: vec![msg_no_stakers.into()],
-            log: vec![],
-            data: None,
-        });
-    }
+    // Stakers decided, the change runs with the admin authority, even once renounced
+    let mut admin_env = env;
+    admin_env.message.sender = deps.api.human_address(&state.admin)?;
+    let mut res = apply_admin_action(deps, admin_env, msg)?;
+    res.log.insert(
+        0,
+        LogAttribute {
+            key: "proposal_id".to_string(),
+            value: proposal_id.to_string(),
+        },
+    );
+    Ok(res)
+}
 
-    let mut claimed_amount = Uint128::zero();
-    for stakeHolder in staking {
-        if !stakeHolder.bonded.is_zero() {
-            let reward = stakeHolder.bonded.multiply_ratio(sent, total_staked);
-            if !reward.is_zero() {
-                claimed_amount = claimed_amount.add(reward);
-                staking_storage(&mut deps.storage).update::<_>(
-                    &stakeHolder.address.as_slice(),
-                    |stake| {
-                        let mut stake_data = stake.unwrap();
-                        stake_data.available = stake_data.available.add(reward);
-                        Ok(stake_data)
-                    },
-                )?;
-            }
-        }
+pub fn handle_cancel_admin_action<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    id: u64,
+) -> StdResult<HandleResponse> {
+    let state = config(&mut deps.storage).load()?;
+    let sender = deps.api.canonical_address(&env.message.sender)?;
+    if state.admin != sender {
+        return Err(StdError::Unauthorized { backtrace: None });
     }
-
-    let final_refund_balance = sent.sub(claimed_amount)?;
-    if final_refund_balance.is_zero() {
-        return Ok(HandleResponse::default());
+    let key = id.to_be_bytes();
+    if admin_action_storage_read(&deps.storage)
+        .may_load(&key)?
+        .is_none()
+    {
+        return Err(StdError::generic_err(format!(
+            "Admin action {} not found",
+            id
+        )));
     }
-
-    let msg = BankMsg::Send {
-        from_address: env.contract.address.clone(),
-        to_address: env.message.sender.clone(),
-        amount: vec![Coin {
-            denom: state.denom_reward,
-            amount: final_refund_balance,
-        }],
-    };
+    admin_action_storage(&mut deps.storage).remove(&key);
 
     Ok(HandleResponse {
-        messages: vec![msg.into()],
-        log: vec![],
+        messages: vec![],
+        log: vec![
+            LogAttribute {
+                key: "action".to_string(),
+                value: "cancel admin action".to_string(),
+            },
+            LogAttribute {
+                key: "id".to_string(),
+                value: id.to_string(),
+            },
+        ],
         data: None,
     })
 }
 
-pub fn query<S: Storage, A: Api, Q: Querier>(
-    deps: &Extern<S, A, Q>,
-    msg: QueryMsg,
-) -> StdResult<Binary> {
-    match msg {
-        QueryMsg::Config {} => to_binary(&query_config(deps)?),
-        QueryMsg::GetHolder { address } => to_binary(&query_holder(deps, address)?),
-        QueryMsg::TransferFrom { .. } => to_binary(&query_transfer_from(deps)?),
-        QueryMsg::Transfer { .. } => to_binary(&query_transfer(deps)?),
-        QueryMsg::GetAllBonded {} => to_binary(&query_all_bonded(deps)?)
+pub fn handle_renounce<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> StdResult<HandleResponse> {
+    // Load the state
+    let mut state = config(&mut deps.storage).load()?;
+    let sender = deps.api.canonical_address(&env.message.sender)?;
+    if state.admin != sender {
+        return Err(StdError::Unauthorized { backtrace: None });
+    }
+    lapse_pause(&mut state, env.block.height);
+    if state.pause != PauseFlags::default() {
+        return Err(StdError::generic_err("Contract is locked"));
     }
+
+    state.admin = deps.api.canonical_address(&env.contract.address)?;
+    // Nobody can take the role back once renounced, nor pause for good
+    state.pending_admin = None;
+    state.guardian = None;
+    clear_admin_actions(&mut deps.storage)?;
+    config(&mut deps.storage).save(&state)?;
+    Ok(HandleResponse::default())
 }
 
-fn query_config<S: Storage, A: Api, Q: Querier>(
-    deps: &Extern<S, A, Q>,
-) -> StdResult<ConfigResponse> {
-    let state = config_read(&deps.storage).load()?;
-    Ok(state)
+/// Drop every queued admin action, they were queued by an admin that lost the role
+fn clear_admin_actions<S: Storage>(storage: &mut S) -> StdResult<()> {
+    let queued = admin_action_storage_read(storage)
+        .range(None, None, Order::Ascending)
+        .map(|item| item.map(|(key, _)| key))
+        .collect::<StdResult<Vec<Vec<u8>>>>()?;
+    for key in queued {
+        admin_action_storage(storage).remove(&key);
+    }
+    Ok(())
 }
 
-fn query_holder<S: Storage, A: Api, Q: Querier>(
-    deps: &Extern<S, A, Q>,
+pub fn handle_propose_new_admin<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
     address: HumanAddr,
-) -> StdResult<GetHolderResponse> {
-    let address_to_canonical = deps.api.canonical_address(&address)?;
-    let store =
-        match staking_storage_read(&deps.storage).may_load(&address_to_canonical.as_slice())? {
-            Some(stake) => Some(stake),
-            None => {
-                return Err(StdError::NotFound {
-                    kind: "not found".to_string(),
-                    backtrace: None,
-                })
-            }
+    expiry: Option<u64>,
+) -> StdResult<HandleResponse> {
+    // Load the state
+    let mut state = config(&mut deps.storage).load()?;
+    let sender = deps.api.canonical_address(&env.message.sender)?;
+    if state.admin != sender {
+        return Err(StdError::Unauthorized { backtrace: None });
+    }
+    if let Some(expiry) = expiry {
+        if expiry <= env.block.height {
+            return Err(StdError::generic_err("Expiry must be in the future"));
         }
-        .unwrap();
+    }
 
-    Ok(GetHolderResponse {
-        address,
-        bonded: store.bonded,
-        un_bonded: store.un_bonded,
-        available: store.available,
-        period: store.period
+    state.pending_admin = Some(PendingAdmin {
+        address: deps.api.canonical_address(&address)?,
+        expiry,
+    });
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            LogAttribute {
+                key: "action".to_string(),
+                value: "propose new admin".to_string(),
+            },
+            LogAttribute {
+                key: "address".to_string(),
+                value: address.to_string(),
+            },
+        ],
+        data: None,
     })
 }
-fn query_all_bonded<S: Storage, A: Api, Q: Querier>(
-    deps: &Extern<S, A, Q>,
-) -> StdResult<GetAllBondedResponse> {
 
-    let total_bonded = staking_storage_read(&deps.storage)
-        .range(None, None, Order::Descending)
-        .flat_map(|item| {
-            item.and_then(|(k, stake)| {
-                Ok(stake.bonded)
-            })
+pub fn handle_accept_admin<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> StdResult<HandleResponse> {
+    // Load the state
+    let mut state = config(&mut deps.storage).load()?;
+    let pending_admin = match state.pending_admin {
+        Some(pending_admin) => pending_admin,
+        None => return Err(StdError::generic_err("No admin proposal")),
+    };
+    let sender = deps.api.canonical_address(&env.message.sender)?;
+    if pending_admin.address != sender {
+        return Err(StdError::Unauthorized { backtrace: None });
+    }
+    if let Some(expiry) = pending_admin.expiry {
+        if expiry <= env.block.height {
+            return Err(StdError::generic_err("Admin proposal expired"));
         }
-    ).collect::<Vec<Uint128>>();
-
-    let mut total = Uint128::zero();
-    for bonded in total_bonded{
-        total = total.add(bonded);
     }
-    Ok(GetAllBondedResponse{
-        total_bonded: total
+
+    state.admin = sender;
+    state.pending_admin = None;
+    // The new admin doesn't inherit the actions queued by the previous one
+    clear_admin_actions(&mut deps.storage)?;
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            LogAttribute {
+                key: "action".to_string(),
+                value: "accept admin".to_string(),
+            },
+            LogAttribute {
+                key: "address".to_string(),
+                value: env.message.sender.to_string(),
+            },
+        ],
+        data: None,
     })
 }
 
-fn query_transfer_from<S: Storage, A: Api, Q: Querier>(
-    _deps: &Extern<S, A, Q>,
-) -> StdResult<StdError> {
-    Err(StdError::Unauthorized { backtrace: None })
-}
-fn query_transfer<S: Storage, A: Api, Q: Querier>(_deps: &Extern<S, A, Q>) -> StdResult<StdError> {
-    Err(StdError::Unauthorized { backtrace: None })
+pub fn handle_cancel_admin_proposal<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> StdResult<HandleResponse> {
+    // Load the state
+    let mut state = config(&mut deps.storage).load()?;
+    let sender = deps.api.canonical_address(&env.message.sender)?;
+    if state.admin != sender {
+        return Err(StdError::Unauthorized { backtrace: None });
+    }
+    if state.pending_admin.is_none() {
+        return Err(StdError::generic_err("No admin proposal"));
+    }
+
+    state.pending_admin = None;
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![LogAttribute {
+            key: "action".to_string(),
+            value: "cancel admin proposal".to_string(),
+        }],
+        data: None,
+    })
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use cosmwasm_std::coins;
-    use cosmwasm_std::testing::{mock_dependencies, mock_env};
-    use cosmwasm_std::StdError::GenericErr;
+pub fn handle_set_guardian<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    guardian: Option<HumanAddr>,
+) -> StdResult<HandleResponse> {
+    // Load the state
+    let mut state = config(&mut deps.storage).load()?;
+    let sender = deps.api.canonical_address(&env.message.sender)?;
+    if state.admin != sender {
+        return Err(StdError::Unauthorized { backtrace: None });
+    }
 
-    struct BeforeAll {
-        default_length: usize,
-        default_sender: HumanAddr,
-        default_sender_two: HumanAddr,
-        default_sender_owner: HumanAddr,
-        default_contract_address: HumanAddr,
-        default_contract_address_two: HumanAddr,
+    state.guardian = match &guardian {
+        Some(guardian) => Some(deps.api.canonical_address(guardian)?),
+        None => None,
+    };
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            LogAttribute {
+                key: "action".to_string(),
+                value: "set guardian".to_string(),
+            },
+            LogAttribute {
+                key: "guardian".to_string(),
+                value: guardian.map(|g| g.to_string()).unwrap_or_default(),
+            },
+        ],
+        data: None,
+    })
+}
+
+/// Streams already opened by a removed payer keep running
+pub fn handle_set_stream_payers<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    payers: Vec<HumanAddr>,
+) -> StdResult<HandleResponse> {
+    // Load the state
+    let mut state = config(&mut deps.storage).load()?;
+    let sender = deps.api.canonical_address(&env.message.sender)?;
+    if state.admin != sender {
+        return Err(StdError::Unauthorized { backtrace: None });
     }
-    fn before_all() -> BeforeAll {
-        BeforeAll {
-            default_length: HumanAddr::from("terra1q88h7ewu6h3am4mxxeqhu3srt7zw4z5s20qu3k").len(),
-            default_sender: HumanAddr::from("terra1q88h7ewu6h3am4mxxeqhu3srt7zw4z5s20q007"),
-            default_sender_two: HumanAddr::from("terra1q88h7ewu6h3am4mxxeqhu3srt7zw4z5s20q008"),
-            default_sender_owner: HumanAddr::from("terra1q88h7ewu6h3am4mxxeqhu3srt7zw4z5s20qu3k"),
-            default_contract_address: HumanAddr::from(
-                "terra1q88h7ewu6h3am4mxxeqhu3srt7zw4z5s20LOTA",
-            ),
-            default_contract_address_two: HumanAddr::from(
-                "terra1q88h7ewu6h3am4mxxeqhu3srt7zw4z5LOTERRA",
-            ),
+
+    let mut stream_payers = vec![];
+    for payer in payers.iter() {
+        let payer = deps.api.canonical_address(payer)?;
+        if stream_payers.contains(&payer) {
+            return Err(StdError::generic_err("Duplicate stream payer"));
         }
+        stream_payers.push(payer);
     }
+    state.stream_payers = stream_payers;
+    config(&mut deps.storage).save(&state)?;
 
-    fn default_init<S: Storage, A: Api, Q: Querier>(mut deps: &mut Extern<S, A, Q>) {
-        let before_all = before_all();
-        let init_msg = InitMsg {
-            address_cw20_loterra_smart_contract: before_all.default_contract_address,
-            unbonded_period: 100,
-            denom_reward: "uusd".to_string(),
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            LogAttribute {
+                key: "action".to_string(),
+                value: "set stream payers".to_string(),
+            },
+            LogAttribute {
+                key: "payers".to_string(),
+                value: payers
+                    .iter()
+                    .map(|payer| payer.to_string())
+                    .collect::<Vec<String>>()
+                    .join(","),
+            },
+        ],
+        data: None,
+    })
+}
+
+/// Check the sender can move the pause flags to the given ones, the guardian can only pause more
+/// and can't engage a new pause right after one lapsed
+fn check_pause_sender(
+    state: &State,
+    sender: &CanonicalAddr,
+    flags: &PauseFlags,
+    height: u64,
+) -> StdResult<()> {
+    if state.admin == *sender {
+        return Ok(());
+    }
+    if state.guardian.as_ref() != Some(sender) {
+        return Err(StdError::Unauthorized { backtrace: None });
+    }
+    if !flags.contains(&state.pause) {
+        return Err(StdError::generic_err("Guardian can only pause"));
+    }
+    // Without flags the expiry left is the one of a lapsed pause
+    if let Some(expiry) = state.pause_expiry {
+        let cooldown_end = expiry + state.max_pause_duration;
+        let pausing = state.pause == PauseFlags::default() && *flags != PauseFlags::default();
+        if pausing && height < cooldown_end {
+            return Err(StdError::generic_err(format!(
+                "Pause lapsed, the guardian can pause again from block {}",
+                cooldown_end
+            )));
+        }
+    }
+    Ok(())
+}
+
+pub fn handle_safe_lock<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> StdResult<HandleResponse> {
+    // Load the state
+    let mut state = config(&mut deps.storage).load()?;
+    let sender = deps.api.canonical_address(&env.message.sender)?;
+    lapse_pause(&mut state, env.block.height);
+
+    let flags = if state.pause == PauseFlags::all() {
+        PauseFlags::default()
+    } else {
+        PauseFlags::all()
+    };
+    check_pause_sender(&state, &sender, &flags, env.block.height)?;
+    set_pause(&mut state, flags, env.block.height);
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse::default())
+}
+
+/// Update the pause flags, tracking since when withdrawals are locked and when the pause lapses
+fn set_pause(state: &mut State, flags: PauseFlags, height: u64) {
+    if flags.blocks_withdrawal() && !state.pause.blocks_withdrawal() {
+        state.locked_since = match (state.locked_since, state.lock_lapsed_at) {
+            // Locked again soon after a lapse, only the unlocked blocks are left out of the clock
+            (Some(since), Some(lapsed_at))
+                if height < lapsed_at + state.emergency_withdraw_delay =>
+            {
+                Some(since + (height - lapsed_at))
+            }
+            _ => Some(height),
         };
-        let res = init(
-            &mut deps,
-            mock_env("terra1q88h7ewu6h3am4mxxeqhu3srt7zw4z5s20qu3k", &[]),
-            init_msg,
-        )
-        .unwrap();
-        assert_eq!(0, res.messages.len());
+        state.lock_lapsed_at = None;
+    } else if !flags.blocks_withdrawal() && state.pause.blocks_withdrawal() {
+        state.locked_since = None;
+        state.lock_lapsed_at = None;
+    }
+    if flags == PauseFlags::default() {
+        // Resuming a lapsed pause keeps its expiry for the guardian cooldown
+        if state.pause != PauseFlags::default() {
+            state.paused_since = None;
+            state.pause_expiry = None;
+        }
+    } else if state.paused_since.is_none() {
+        state.paused_since = Some(height);
+        state.pause_expiry = Some(height + state.max_pause_duration);
     }
+    state.pause = flags;
+}
 
-    #[test]
-    fn proper_initialization() {
-        let before_all = before_all();
-        let mut deps = mock_dependencies(before_all.default_length, &[]);
-        let env = mock_env("creator", &coins(1000, "earth"));
-        default_init(&mut deps);
+/// Pause flags in effect at height, nothing is paused once the pause expiry is reached
+fn active_pause(state: &State, height: u64) -> PauseFlags {
+    match state.pause_expiry {
+        Some(expiry) if height >= expiry => PauseFlags::default(),
+        _ => state.pause.clone(),
+    }
+}
+
+/// Clear a pause whose expiry is reached before changing it, the expiry and withdrawal clock are kept
+fn lapse_pause(state: &mut State, height: u64) {
+    if state.pause == PauseFlags::default() || active_pause(state, height) != PauseFlags::default()
+    {
+        return;
+    }
+    if state.pause.blocks_withdrawal() {
+        state.lock_lapsed_at = state.pause_expiry;
+    }
+    state.pause = PauseFlags::default();
+    state.paused_since = None;
+}
+
+pub fn handle_extend_pause<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> StdResult<HandleResponse> {
+    // Load the state
+    let mut state = config(&mut deps.storage).load()?;
+    let sender = deps.api.canonical_address(&env.message.sender)?;
+    if state.admin != sender {
+        return Err(StdError::Unauthorized { backtrace: None });
+    }
+    lapse_pause(&mut state, env.block.height);
+    if state.pause == PauseFlags::default() {
+        return Err(StdError::generic_err("Contract is not paused"));
+    }
+
+    let expiry = env.block.height + state.max_pause_duration;
+    state.pause_expiry = Some(expiry);
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            LogAttribute {
+                key: "action".to_string(),
+                value: "extend pause".to_string(),
+            },
+            LogAttribute {
+                key: "pause_expiry".to_string(),
+                value: expiry.to_string(),
+            },
+        ],
+        data: None,
+    })
+}
+
+pub fn handle_set_pause_flags<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    flags: PauseFlags,
+) -> StdResult<HandleResponse> {
+    // Load the state
+    let mut state = config(&mut deps.storage).load()?;
+    let sender = deps.api.canonical_address(&env.message.sender)?;
+    lapse_pause(&mut state, env.block.height);
+    check_pause_sender(&state, &sender, &flags, env.block.height)?;
+
+    set_pause(&mut state, flags, env.block.height);
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![LogAttribute {
+            key: "action".to_string(),
+            value: "set pause flags".to_string(),
+        }],
+        data: None,
+    })
+}
+
+pub fn handle_set_instant_unstake_penalty<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    rate: Decimal,
+    destination: PenaltyDestination,
+    treasury: Option<HumanAddr>,
+) -> StdResult<HandleResponse> {
+    // Load the state
+    let mut state = config(&mut deps.storage).load()?;
+    let sender = deps.api.canonical_address(&env.message.sender)?;
+    if state.admin != sender {
+        return Err(StdError::Unauthorized { backtrace: None });
+    }
+    if rate > Decimal::one() {
+        return Err(StdError::generic_err("Penalty rate can't exceed 100%"));
+    }
+    state.treasury = match treasury {
+        Some(treasury) => Some(deps.api.canonical_address(&treasury)?),
+        None => None,
+    };
+    if destination == PenaltyDestination::Treasury && state.treasury.is_none() {
+        return Err(StdError::generic_err("Treasury address required"));
+    }
+
+    state.instant_unstake_penalty = rate;
+    state.penalty_destination = destination;
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            LogAttribute {
+                key: "action".to_string(),
+                value: "set instant unstake penalty".to_string(),
+            },
+            LogAttribute {
+                key: "rate".to_string(),
+                value: rate.to_string(),
+            },
+        ],
+        data: None,
+    })
+}
+
+/// Optional fields of UpdateConfig
+pub struct ConfigUpdate {
+    pub unbonded_period: Option<u64>,
+    pub reward_denoms: Option<Vec<String>>,
+    pub reward_tokens: Option<Vec<HumanAddr>>,
+    pub address_cw20_loterra_smart_contract: Option<HumanAddr>,
+    pub max_unbonding_entries: Option<u64>,
+    pub carry_reward_remainder: Option<bool>,
+    pub lock_tiers: Option<Vec<LockTier>>,
+    pub min_bonding_age: Option<u64>,
+    pub timelock_delay: Option<u64>,
+    pub emergency_withdraw_delay: Option<u64>,
+    pub max_pause_duration: Option<u64>,
+    pub governance: Option<GovernanceConfig>,
+}
+
+/// Log the old and new value of an updated parameter
+fn log_update(log: &mut Vec<LogAttribute>, key: &str, old: String, new: String) {
+    log.push(LogAttribute {
+        key: format!("old_{}", key),
+        value: old,
+    });
+    log.push(LogAttribute {
+        key: format!("new_{}", key),
+        value: new,
+    });
+}
+
+pub fn handle_update_config<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    update: ConfigUpdate,
+) -> StdResult<HandleResponse> {
+    // Load the state
+    let mut state = config(&mut deps.storage).load()?;
+    let sender = deps.api.canonical_address(&env.message.sender)?;
+    if state.admin != sender {
+        return Err(StdError::Unauthorized { backtrace: None });
+    }
+    if !env.message.sent_funds.is_empty() {
+        return Err(StdError::generic_err("Do not send funds"));
+    }
+
+    let mut log = vec![LogAttribute {
+        key: "action".to_string(),
+        value: "update config".to_string(),
+    }];
+    // Entries already unBonding keep the release height they were given
+    if let Some(unbonded_period) = update.unbonded_period {
+        log_update(
+            &mut log,
+            "unbonded_period",
+            state.unbonded_period.to_string(),
+            unbonded_period.to_string(),
+        );
+        state.unbonded_period = unbonded_period;
+    }
+    if let Some(reward_denoms) = update.reward_denoms {
+        let reward_indexes = whitelist_reward_denoms(reward_denoms, &state.reward_indexes)?;
+        // Holders settle lazily, rewards still owed in a denom can't be told, so denoms are only added
+        for reward in state.reward_indexes.iter() {
+            if !reward_indexes.iter().any(|r| r.denom == reward.denom) {
+                return Err(StdError::generic_err(format!(
+                    "Can't remove reward denom {}, denoms can only be added",
+                    reward.denom
+                )));
+            }
+        }
+        let denoms = |indexes: &[RewardIndex]| {
+            indexes
+                .iter()
+                .map(|r| r.denom.clone())
+                .collect::<Vec<String>>()
+                .join(",")
+        };
+        log_update(
+            &mut log,
+            "reward_denoms",
+            denoms(&state.reward_indexes),
+            denoms(&reward_indexes),
+        );
+        state.reward_indexes = reward_indexes;
+    }
+    if let Some(reward_tokens) = update.reward_tokens {
+        let token_reward_indexes =
+            whitelist_reward_tokens(&deps.api, reward_tokens, &state.token_reward_indexes)?;
+        // Same as denoms, tokens still owed to holders can't be told
+        for reward in state.token_reward_indexes.iter() {
+            if !token_reward_indexes.iter().any(|r| r.token == reward.token) {
+                return Err(StdError::generic_err(format!(
+                    "Can't remove reward token {}, tokens can only be added",
+                    deps.api.human_address(&reward.token)?
+                )));
+            }
+        }
+        let tokens = |indexes: &[TokenRewardIndex]| -> StdResult<String> {
+            Ok(indexes
+                .iter()
+                .map(|r| Ok(deps.api.human_address(&r.token)?.to_string()))
+                .collect::<StdResult<Vec<String>>>()?
+                .join(","))
+        };
+        log_update(
+            &mut log,
+            "reward_tokens",
+            tokens(&state.token_reward_indexes)?,
+            tokens(&token_reward_indexes)?,
+        );
+        state.token_reward_indexes = token_reward_indexes;
+    }
+    if let Some(address) = update.address_cw20_loterra_smart_contract {
+        if !state.total_bonded.is_zero() || !state.total_unbonding.is_zero() {
+            return Err(StdError::generic_err(
+                "Can't change the LoTerra cw-20 while tokens are staked",
+            ));
+        }
+        let old = deps
+            .api
+            .human_address(&state.address_cw20_loterra_smart_contract)?;
+        state.address_cw20_loterra_smart_contract = deps.api.canonical_address(&address)?;
+        log_update(
+            &mut log,
+            "address_cw20_loterra_smart_contract",
+            old.to_string(),
+            address.to_string(),
+        );
+    }
+    if let Some(max_unbonding_entries) = update.max_unbonding_entries {
+        if max_unbonding_entries == 0 {
+            return Err(StdError::generic_err(
+                "Max unBonding entries must be at least 1",
+            ));
+        }
+        log_update(
+            &mut log,
+            "max_unbonding_entries",
+            state.max_unbonding_entries.to_string(),
+            max_unbonding_entries.to_string(),
+        );
+        state.max_unbonding_entries = max_unbonding_entries;
+    }
+    if let Some(carry_reward_remainder) = update.carry_reward_remainder {
+        log_update(
+            &mut log,
+            "carry_reward_remainder",
+            state.carry_reward_remainder.to_string(),
+            carry_reward_remainder.to_string(),
+        );
+        state.carry_reward_remainder = carry_reward_remainder;
+    }
+    // Existing locks keep the weight and expiry they were made with
+    if let Some(lock_tiers) = update.lock_tiers {
+        validate_lock_tiers(&lock_tiers)?;
+        let tiers = |tiers: &[LockTier]| {
+            tiers
+                .iter()
+                .map(|t| format!("{}:{}", t.duration, t.weight))
+                .collect::<Vec<String>>()
+                .join(",")
+        };
+        log_update(
+            &mut log,
+            "lock_tiers",
+            tiers(&state.lock_tiers),
+            tiers(&lock_tiers),
+        );
+        state.lock_tiers = lock_tiers;
+    }
+    // Only new deposits wait for the new age
+    if let Some(min_bonding_age) = update.min_bonding_age {
+        log_update(
+            &mut log,
+            "min_bonding_age",
+            state.min_bonding_age.to_string(),
+            min_bonding_age.to_string(),
+        );
+        state.min_bonding_age = min_bonding_age;
+    }
+    // Actions already queued keep their execute after height
+    if let Some(timelock_delay) = update.timelock_delay {
+        log_update(
+            &mut log,
+            "timelock_delay",
+            state.timelock_delay.to_string(),
+            timelock_delay.to_string(),
+        );
+        state.timelock_delay = timelock_delay;
+    }
+    if let Some(emergency_withdraw_delay) = update.emergency_withdraw_delay {
+        if emergency_withdraw_delay == 0 {
+            return Err(StdError::generic_err(
+                "Emergency withdraw delay must be at least 1",
+            ));
+        }
+        log_update(
+            &mut log,
+            "emergency_withdraw_delay",
+            state.emergency_withdraw_delay.to_string(),
+            emergency_withdraw_delay.to_string(),
+        );
+        state.emergency_withdraw_delay = emergency_withdraw_delay;
+    }
+    if let Some(max_pause_duration) = update.max_pause_duration {
+        if max_pause_duration == 0 {
+            return Err(StdError::generic_err(
+                "Max pause duration must be at least 1",
+            ));
+        }
+        log_update(
+            &mut log,
+            "max_pause_duration",
+            state.max_pause_duration.to_string(),
+            max_pause_duration.to_string(),
+        );
+        state.max_pause_duration = max_pause_duration;
+    }
+    if let Some(governance) = update.governance {
+        validate_governance(&governance)?;
+        log_update(
+            &mut log,
+            "governance",
+            format!("{:?}", state.governance),
+            format!("{:?}", governance),
+        );
+        state.governance = governance;
+    }
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log,
+        data: None,
+    })
+}
+
+pub fn handle_recompute_totals<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> StdResult<HandleResponse> {
+    // Load the state
+    let mut state = config(&mut deps.storage).load()?;
+    let sender = deps.api.canonical_address(&env.message.sender)?;
+    if state.admin != sender {
+        return Err(StdError::Unauthorized { backtrace: None });
+    }
+    advance(&mut deps.storage, &mut state, env.block.height)?;
+
+    let mut total_bonded = Uint128::zero();
+    let mut total_weighted = Uint128::zero();
+    let mut total_warming = Uint128::zero();
+    let mut total_unbonding = Uint128::zero();
+    for item in staking_storage_read(&deps.storage).range(None, None, Order::Ascending) {
+        let (_, mut stake) = item?;
+        // Deposits of matured batches count as eligible even before the holder settles
+        settle_matured(&deps.storage, &state, &mut stake)?;
+        total_bonded = total_bonded.add(stake.bonded);
+        total_weighted = total_weighted.add(weighted_bonded(&stake));
+        total_warming = total_warming.add(warming_amount(&stake));
+        total_unbonding = total_unbonding.add(stake.un_bonded);
+    }
+
+    state.total_bonded = total_bonded;
+    state.total_weighted = total_weighted;
+    state.total_warming = total_warming;
+    state.total_unbonding = total_unbonding;
+    total_voting_power_storage(&mut deps.storage)
+        .save(&env.block.height.to_be_bytes(), &total_bonded)?;
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            LogAttribute {
+                key: "action".to_string(),
+                value: "recompute totals".to_string(),
+            },
+            LogAttribute {
+                key: "total_bonded".to_string(),
+                value: total_bonded.to_string(),
+            },
+            LogAttribute {
+                key: "total_unbonding".to_string(),
+                value: total_unbonding.to_string(),
+            },
+        ],
+        data: None,
+    })
+}
+
+/// Add amount to the staker bonded balance, shared by every stake path
+fn bond<S: Storage>(
+    storage: &mut S,
+    state: &mut State,
+    staker: &CanonicalAddr,
+    amount: Uint128,
+    height: u64,
+) -> StdResult<()> {
+    advance(storage, state, height)?;
+    let mut stake_data = match staking_storage(storage).may_load(staker.as_slice())? {
+        Some(stake) => stake,
+        None => StakingInfo {
+            bonded: Uint128::zero(),
+            un_bonded: Uint128::zero(),
+            unbonding: vec![],
+            available: vec![],
+            reward_indexes: vec![],
+            available_tokens: vec![],
+            token_reward_indexes: vec![],
+            lock: None,
+            warming: vec![],
+        },
+    };
+    settle(storage, state, &mut stake_data)?;
+    let previous = stake_data.bonded;
+    stake_data.bonded = stake_data.bonded.add(amount);
+    state.total_bonded = state.total_bonded.add(amount);
+    if state.min_bonding_age == 0 {
+        state.total_weighted = state.total_weighted.add(amount);
+    } else {
+        warm_up(storage, state, &mut stake_data, amount, height)?;
+    }
+    checkpoint(storage, state, staker, stake_data.bonded, height)?;
+    reindex_bonded(storage, staker, previous, stake_data.bonded)?;
+    staking_storage(storage).save(staker.as_slice(), &stake_data)?;
+    config(storage).save(state)
+}
+
+/// Record the bonded amount of staker and the total bonded as voting power at height
+fn checkpoint<S: Storage>(
+    storage: &mut S,
+    state: &State,
+    staker: &CanonicalAddr,
+    bonded: Uint128,
+    height: u64,
+) -> StdResult<()> {
+    voting_power_storage(storage, staker).save(&height.to_be_bytes(), &bonded)?;
+    total_voting_power_storage(storage).save(&height.to_be_bytes(), &state.total_bonded)
+}
+
+/// Checkpoint every holder and the total once, for stores written before voting power existed
+fn backfill_voting_power<S: Storage>(storage: &mut S, height: u64) -> StdResult<()> {
+    if total_voting_power_storage_read(storage)
+        .range(None, None, Order::Ascending)
+        .next()
+        .is_some()
+    {
+        return Ok(());
+    }
+    let state = config_read(storage).load()?;
+    let holders = staking_storage_read(storage)
+        .range(None, None, Order::Ascending)
+        .map(|item| item.map(|(key, stake)| (CanonicalAddr::from(key), stake.bonded)))
+        .collect::<StdResult<Vec<(CanonicalAddr, Uint128)>>>()?;
+    for (staker, bonded) in holders {
+        voting_power_storage(storage, &staker).save(&height.to_be_bytes(), &bonded)?;
+    }
+    total_voting_power_storage(storage).save(&height.to_be_bytes(), &state.total_bonded)
+}
+
+/// Remove amount from the staker bonded balance, shared by every unStake path
+fn unbond<S: Storage>(
+    storage: &mut S,
+    state: &mut State,
+    staker: &CanonicalAddr,
+    stake: &mut StakingInfo,
+    amount: Uint128,
+    height: u64,
+) -> StdResult<()> {
+    if stake.bonded < amount {
+        return Err(StdError::generic_err(format!(
+            "You can't unStake more than you have ({})",
+            stake.bonded
+        )));
+    }
+    if let Some(lock) = &stake.lock {
+        if lock.expiry > height && stake.bonded.sub(lock.amount)? < amount {
+            return Err(StdError::generic_err(format!(
+                "Locked tokens can't be unStaked before block {}",
+                lock.expiry
+            )));
+        }
+    }
+    advance(storage, state, height)?;
+    settle(storage, state, stake)?;
+    // Warming deposits leave first, the rest was earning rewards
+    let cooled = cool_down(storage, state, stake, amount)?;
+    let previous = stake.bonded;
+    stake.bonded = stake.bonded.sub(amount)?;
+
+    state.total_bonded = state.total_bonded.sub(amount)?;
+    state.total_weighted = state.total_weighted.sub(amount.sub(cooled)?)?;
+    checkpoint(storage, state, staker, stake.bonded, height)?;
+    reindex_bonded(storage, staker, previous, stake.bonded)
+}
+
+/// Move staker in the bonded index from its previous bonded amount, holders without bonded are left out
+fn reindex_bonded<S: Storage>(
+    storage: &mut S,
+    staker: &CanonicalAddr,
+    previous: Uint128,
+    bonded: Uint128,
+) -> StdResult<()> {
+    if !previous.is_zero() {
+        bonded_index_storage(storage).remove(&bonded_index_key(previous, staker));
+    }
+    if !bonded.is_zero() {
+        bonded_index_storage(storage).save(&bonded_index_key(bonded, staker), staker)?;
+    }
+    Ok(())
+}
+
+/// Index every holder once, for stores written before the bonded index existed
+fn backfill_bonded_index<S: Storage>(storage: &mut S) -> StdResult<()> {
+    if bonded_index_storage_read(storage)
+        .range(None, None, Order::Ascending)
+        .next()
+        .is_some()
+    {
+        return Ok(());
+    }
+    let holders = staking_storage_read(storage)
+        .range(None, None, Order::Ascending)
+        .map(|item| item.map(|(key, stake)| (CanonicalAddr::from(key), stake.bonded)))
+        .collect::<StdResult<Vec<(CanonicalAddr, Uint128)>>>()?;
+    for (staker, bonded) in holders {
+        reindex_bonded(storage, &staker, Uint128::zero(), bonded)?;
+    }
+    Ok(())
+}
+
+pub fn handle_receive<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    wrapper: Cw20ReceiveMsg,
+) -> StdResult<HandleResponse> {
+    let state = config(&mut deps.storage).load()?;
+
+    let msg: ReceiveMsg = match wrapper.msg {
+        Some(msg) => from_binary(&msg)?,
+        None => return Err(StdError::generic_err("Receive message required")),
+    };
+    let sender = deps.api.canonical_address(&env.message.sender)?;
+    match msg {
+        ReceiveMsg::Stake {} => {
+            // Only the loterra cw-20 can be staked
+            if state.address_cw20_loterra_smart_contract != sender {
+                return Err(StdError::Unauthorized { backtrace: None });
+            }
+            handle_receive_stake(deps, env, wrapper.sender, wrapper.amount)
+        }
+        ReceiveMsg::PayoutReward {} => {
+            // Only whitelisted cw-20 can be paid out
+            if !state.token_reward_indexes.iter().any(|r| r.token == sender) {
+                return Err(StdError::Unauthorized { backtrace: None });
+            }
+            handle_receive_payout_reward(deps, env, wrapper.sender, wrapper.amount)
+        }
+    }
+}
+
+pub fn handle_receive_payout_reward<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    payer: HumanAddr,
+    amount: Uint128,
+) -> StdResult<HandleResponse> {
+    let mut state = config(&mut deps.storage).load()?;
+    if active_pause(&state, env.block.height).payout {
+        return Err(StdError::generic_err(
+            "Contract deactivated for update or/and preventing security issue",
+        ));
+    }
+    if !env.message.sent_funds.is_empty() {
+        return Err(StdError::generic_err("Do not send funds with payout"));
+    }
+    if amount.is_zero() {
+        return Err(StdError::generic_err("Amount required"));
+    }
+
+    let token = deps.api.canonical_address(&env.message.sender)?;
+    advance(&mut deps.storage, &mut state, env.block.height)?;
+    let refund = if state.total_weighted.is_zero() {
+        amount
+    } else {
+        let total_weighted = state.total_weighted;
+        let reward = state
+            .token_reward_indexes
+            .iter_mut()
+            .find(|r| r.token == token)
+            .unwrap();
+        distribute_reward(
+            total_weighted,
+            amount,
+            &mut reward.index,
+            &mut reward.remainder,
+        )
+    };
+    config(&mut deps.storage).save(&state)?;
+
+    let mut messages = vec![];
+    if !refund.is_zero() {
+        let msg = Cw20ExecuteMsg::Transfer {
+            recipient: payer.clone(),
+            amount: refund,
+        };
+        messages.push(encode_msg_execute(msg, env.message.sender.clone())?);
+    }
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![
+            LogAttribute {
+                key: "action".to_string(),
+                value: "payout token reward".to_string(),
+            },
+            LogAttribute {
+                key: "from".to_string(),
+                value: payer.to_string(),
+            },
+            LogAttribute {
+                key: "token".to_string(),
+                value: env.message.sender.to_string(),
+            },
+            LogAttribute {
+                key: "amount".to_string(),
+                value: amount.to_string(),
+            },
+            LogAttribute {
+                key: "refund".to_string(),
+                value: refund.to_string(),
+            },
+        ],
+        data: None,
+    })
+}
+
+pub fn handle_receive_stake<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    staker: HumanAddr,
+    amount: Uint128,
+) -> StdResult<HandleResponse> {
+    let mut state = config(&mut deps.storage).load()?;
+
+    if active_pause(&state, env.block.height).stake {
+        return Err(StdError::generic_err(
+            "Contract deactivated for update or/and preventing security issue",
+        ));
+    }
+
+    if !env.message.sent_funds.is_empty() {
+        return Err(StdError::generic_err("Do not send funds with stake"));
+    }
+    if amount.is_zero() {
+        return Err(StdError::generic_err("Amount required"));
+    }
+
+    // Tokens are already transferred by the cw-20 Send
+    let staker_canonical = deps.api.canonical_address(&staker)?;
+    bond(
+        &mut deps.storage,
+        &mut state,
+        &staker_canonical,
+        amount,
+        env.block.height,
+    )?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            LogAttribute {
+                key: "action".to_string(),
+                value: "bond lota".to_string(),
+            },
+            LogAttribute {
+                key: "from".to_string(),
+                value: staker.to_string(),
+            },
+            LogAttribute {
+                key: "to".to_string(),
+                value: env.contract.address.to_string(),
+            },
+            LogAttribute {
+                key: "amount".to_string(),
+                value: amount.to_string(),
+            },
+        ],
+        data: None,
+    })
+}
+
+pub fn handle_stake<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    amount: Uint128,
+) -> StdResult<HandleResponse> {
+    let mut state = config(&mut deps.storage).load()?;
+
+    if active_pause(&state, env.block.height).stake {
+        return Err(StdError::generic_err(
+            "Contract deactivated for update or/and preventing security issue",
+        ));
+    }
+
+    if !env.message.sent_funds.is_empty() {
+        return Err(StdError::generic_err("Do not send funds with stake"));
+    }
+    if amount.is_zero(){
+        return Err(StdError::generic_err("Amount required"));
+    }
+    // Prepare msg to send
+    let msg = Cw20ExecuteMsg::TransferFrom {
+        owner: env.message.sender.clone(),
+        recipient: env.contract.address.clone(),
+        amount,
+    };
+    // Convert state address of loterra cw-20
+    let lottera_human = deps
+        .api
+        .human_address(&state.address_cw20_loterra_smart_contract.clone())?;
+    // Prepare the message
+    let res = encode_msg_execute(msg, lottera_human)?;
+
+    let sender_canonical = deps.api.canonical_address(&env.message.sender)?;
+    bond(
+        &mut deps.storage,
+        &mut state,
+        &sender_canonical,
+        amount,
+        env.block.height,
+    )?;
+
+    Ok(HandleResponse {
+        messages: vec![res],
+        log: vec![
+            LogAttribute {
+                key: "action".to_string(),
+                value: "bond lota".to_string(),
+            },
+            LogAttribute {
+                key: "from".to_string(),
+                value: env.message.sender.to_string(),
+            },
+            LogAttribute {
+                key: "to".to_string(),
+                value: env.contract.address.to_string(),
+            },
+            LogAttribute {
+                key: "amount".to_string(),
+                value: amount.to_string(),
+            },
+        ],
+        data: None,
+    })
+}
+
+pub fn handle_unstake<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    amount: Uint128,
+) -> StdResult<HandleResponse> {
+    let mut state = config(&mut deps.storage).load()?;
+
+    if active_pause(&state, env.block.height).unstake {
+        return Err(StdError::generic_err(
+            "Contract deactivated for update or/and preventing security issue",
+        ));
+    }
+
+    if !env.message.sent_funds.is_empty() {
+        return Err(StdError::generic_err("Do not send funds with un_stake"));
+    }
+    if amount.is_zero(){
+        return Err(StdError::generic_err("Amount required"));
+    }
+
+    let sender_canonical = deps.api.canonical_address(&env.message.sender)?;
+    let mut stake_data =
+        match staking_storage(&mut deps.storage).may_load(sender_canonical.as_slice())? {
+            Some(stake) => stake,
+            None => {
+                return Err(StdError::Unauthorized { backtrace: None });
+            }
+        };
+    unbond(
+        &mut deps.storage,
+        &mut state,
+        &sender_canonical,
+        &mut stake_data,
+        amount,
+        env.block.height,
+    )?;
+    let release_height = env.block.height + state.unbonded_period;
+    // Unstakes maturing at the same height share one entry
+    match stake_data
+        .unbonding
+        .iter_mut()
+        .find(|entry| entry.release_height == release_height)
+    {
+        Some(entry) => entry.amount = entry.amount.add(amount),
+        None => {
+            if stake_data.unbonding.len() as u64 >= state.max_unbonding_entries {
+                return Err(StdError::generic_err(format!(
+                    "Too many unBonding entries (max {}), claim released tokens first",
+                    state.max_unbonding_entries
+                )));
+            }
+            stake_data.unbonding.push(UnbondingInfo {
+                amount,
+                release_height,
+            });
+        }
+    };
+    stake_data.un_bonded = stake_data.un_bonded.add(amount);
+    staking_storage(&mut deps.storage).save(sender_canonical.as_slice(), &stake_data)?;
+
+    state.total_unbonding = state.total_unbonding.add(amount);
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            LogAttribute {
+                key: "action".to_string(),
+                value: "unbond lota".to_string(),
+            },
+            LogAttribute {
+                key: "amount".to_string(),
+                value: amount.to_string(),
+            },
+            LogAttribute {
+                key: "release_height".to_string(),
+                value: release_height.to_string(),
+            },
+        ],
+        data: None,
+    })
+}
+
+pub fn handle_lock<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    amount: Uint128,
+    tier: u64,
+) -> StdResult<HandleResponse> {
+    let mut state = config(&mut deps.storage).load()?;
+
+    if active_pause(&state, env.block.height).stake {
+        return Err(StdError::generic_err(
+            "Contract deactivated for update or/and preventing security issue",
+        ));
+    }
+
+    if !env.message.sent_funds.is_empty() {
+        return Err(StdError::generic_err("Do not send funds with lock"));
+    }
+    if amount.is_zero() {
+        return Err(StdError::generic_err("Amount required"));
+    }
+    let lock_tier = match state.lock_tiers.get(tier as usize) {
+        Some(lock_tier) => lock_tier.clone(),
+        None => {
+            return Err(StdError::generic_err(format!(
+                "Lock tier {} not found",
+                tier
+            )))
+        }
+    };
+
+    let sender_canonical = deps.api.canonical_address(&env.message.sender)?;
+    let mut stake_data =
+        match staking_storage(&mut deps.storage).may_load(sender_canonical.as_slice())? {
+            Some(stake) => stake,
+            None => {
+                return Err(StdError::Unauthorized { backtrace: None });
+            }
+        };
+    advance(&mut deps.storage, &mut state, env.block.height)?;
+    settle(&mut deps.storage, &state, &mut stake_data)?;
+
+    let locked = match &stake_data.lock {
+        Some(lock) if lock.tier > tier => {
+            return Err(StdError::generic_err(format!(
+                "Lock tier can't be lower than the current one ({})",
+                lock.tier
+            )));
+        }
+        Some(lock) => lock.amount,
+        None => Uint128::zero(),
+    };
+    let unlocked = stake_data
+        .bonded
+        .sub(locked)?
+        .sub(warming_amount(&stake_data))?;
+    if unlocked < amount {
+        return Err(StdError::generic_err(format!(
+            "You can't lock more than your unlocked bonded ({})",
+            unlocked
+        )));
+    }
+
+    // The whole lock moves to the new tier and restarts its term
+    if let Some(lock) = &stake_data.lock {
+        remove_lock_expiry(&mut deps.storage, lock)?;
+    }
+    let weighted = weighted_bonded(&stake_data);
+    let expiry = env.block.height + lock_tier.duration;
+    let lock = LockInfo {
+        tier,
+        amount: locked.add(amount),
+        expiry,
+        weight: lock_tier.weight,
+    };
+    add_lock_expiry(&mut deps.storage, &lock)?;
+    stake_data.lock = Some(lock);
+    state.total_weighted = state
+        .total_weighted
+        .sub(weighted)?
+        .add(weighted_bonded(&stake_data));
+    staking_storage(&mut deps.storage).save(sender_canonical.as_slice(), &stake_data)?;
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            LogAttribute {
+                key: "action".to_string(),
+                value: "lock".to_string(),
+            },
+            LogAttribute {
+                key: "amount".to_string(),
+                value: amount.to_string(),
+            },
+            LogAttribute {
+                key: "tier".to_string(),
+                value: tier.to_string(),
+            },
+            LogAttribute {
+                key: "expiry".to_string(),
+                value: expiry.to_string(),
+            },
+        ],
+        data: None,
+    })
+}
+
+pub fn handle_expire_lock<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    address: HumanAddr,
+) -> StdResult<HandleResponse> {
+    let mut state = config(&mut deps.storage).load()?;
+
+    if active_pause(&state, env.block.height).stake {
+        return Err(StdError::generic_err(
+            "Contract deactivated for update or/and preventing security issue",
+        ));
+    }
+
+    if !env.message.sent_funds.is_empty() {
+        return Err(StdError::generic_err("Do not send funds"));
+    }
+
+    let address_canonical = deps.api.canonical_address(&address)?;
+    let mut stake_data = staking_storage(&mut deps.storage).load(address_canonical.as_slice())?;
+    match &stake_data.lock {
+        Some(lock) if lock.expiry <= env.block.height => {}
+        _ => return Err(StdError::generic_err("No expired lock")),
+    }
+    // Settling drops the lock, its batch expired when advancing
+    advance(&mut deps.storage, &mut state, env.block.height)?;
+    settle(&mut deps.storage, &state, &mut stake_data)?;
+    staking_storage(&mut deps.storage).save(address_canonical.as_slice(), &stake_data)?;
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            LogAttribute {
+                key: "action".to_string(),
+                value: "expire lock".to_string(),
+            },
+            LogAttribute {
+                key: "address".to_string(),
+                value: address.to_string(),
+            },
+        ],
+        data: None,
+    })
+}
+
+pub fn handle_instant_unstake<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    amount: Uint128,
+) -> StdResult<HandleResponse> {
+    let mut state = config(&mut deps.storage).load()?;
+
+    if active_pause(&state, env.block.height).unstake {
+        return Err(StdError::generic_err(
+            "Contract deactivated for update or/and preventing security issue",
+        ));
+    }
+
+    if !env.message.sent_funds.is_empty() {
+        return Err(StdError::generic_err("Do not send funds with un_stake"));
+    }
+    if amount.is_zero() {
+        return Err(StdError::generic_err("Amount required"));
+    }
+
+    let sender_canonical = deps.api.canonical_address(&env.message.sender)?;
+    let mut stake_data =
+        match staking_storage(&mut deps.storage).may_load(sender_canonical.as_slice())? {
+            Some(stake) => stake,
+            None => {
+                return Err(StdError::Unauthorized { backtrace: None });
+            }
+        };
+    unbond(
+        &mut deps.storage,
+        &mut state,
+        &sender_canonical,
+        &mut stake_data,
+        amount,
+        env.block.height,
+    )?;
+    staking_storage(&mut deps.storage).save(sender_canonical.as_slice(), &stake_data)?;
+
+    let penalty = amount * state.instant_unstake_penalty;
+    let received = amount.sub(penalty)?;
+    let lottera_human = deps
+        .api
+        .human_address(&state.address_cw20_loterra_smart_contract)?;
+    let mut messages = vec![encode_msg_execute(
+        Cw20ExecuteMsg::Transfer {
+            recipient: env.message.sender.clone(),
+            amount: received,
+        },
+        lottera_human.clone(),
+    )?];
+    if !penalty.is_zero() {
+        match state.penalty_destination {
+            PenaltyDestination::Redistribute => {
+                let lota = state.address_cw20_loterra_smart_contract.clone();
+                let position = match state
+                    .token_reward_indexes
+                    .iter()
+                    .position(|r| r.token == lota)
+                {
+                    Some(position) => position,
+                    None => {
+                        // Holders without checkpoint start from zero, as this index does
+                        state.token_reward_indexes.push(TokenRewardIndex {
+                            token: lota,
+                            index: Uint128::zero(),
+                            remainder: Uint128::zero(),
+                        });
+                        state.token_reward_indexes.len() - 1
+                    }
+                };
+                let total_weighted = state.total_weighted;
+                let reward = &mut state.token_reward_indexes[position];
+                if total_weighted.is_zero() {
+                    // Nobody left to share with, the penalty waits for the next distribution
+                    reward.remainder = reward
+                        .remainder
+                        .add(Uint128(penalty.u128() * REWARD_INDEX_SCALE));
+                } else {
+                    let dust = distribute_reward(
+                        total_weighted,
+                        penalty,
+                        &mut reward.index,
+                        &mut reward.remainder,
+                    );
+                    reward.remainder = reward
+                        .remainder
+                        .add(Uint128(dust.u128() * REWARD_INDEX_SCALE));
+                }
+            }
+            PenaltyDestination::Burn => {
+                messages.push(encode_msg_execute(
+                    Cw20ExecuteMsg::Burn { amount: penalty },
+                    lottera_human,
+                )?);
+            }
+            PenaltyDestination::Treasury => {
+                let treasury = match &state.treasury {
+                    Some(treasury) => deps.api.human_address(treasury)?,
+                    None => return Err(StdError::generic_err("Treasury address required")),
+                };
+                messages.push(encode_msg_execute(
+                    Cw20ExecuteMsg::Transfer {
+                        recipient: treasury,
+                        amount: penalty,
+                    },
+                    lottera_human,
+                )?);
+            }
+        }
+    }
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![
+            LogAttribute {
+                key: "action".to_string(),
+                value: "instant unbond lota".to_string(),
+            },
+            LogAttribute {
+                key: "amount".to_string(),
+                value: amount.to_string(),
+            },
+            LogAttribute {
+                key: "penalty".to_string(),
+                value: penalty.to_string(),
+            },
+            LogAttribute {
+                key: "received".to_string(),
+                value: received.to_string(),
+            },
+        ],
+        data: None,
+    })
+}
+
+pub fn handle_rebond<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    amount: Uint128,
+) -> StdResult<HandleResponse> {
+    let mut state = config(&mut deps.storage).load()?;
+
+    if active_pause(&state, env.block.height).stake {
+        return Err(StdError::generic_err(
+            "Contract deactivated for update or/and preventing security issue",
+        ));
+    }
+
+    if !env.message.sent_funds.is_empty() {
+        return Err(StdError::generic_err("Do not send funds with rebond"));
+    }
+    if amount.is_zero() {
+        return Err(StdError::generic_err("Amount required"));
+    }
+
+    let sender_canonical = deps.api.canonical_address(&env.message.sender)?;
+    let mut store =
+        match staking_storage(&mut deps.storage).may_load(sender_canonical.as_slice())? {
+            Some(stake) => stake,
+            None => {
+                return Err(StdError::Unauthorized { backtrace: None });
+            }
+        };
+    if store.un_bonded < amount {
+        return Err(StdError::generic_err(format!(
+            "You can't reBond more than you have unBonding ({})",
+            store.un_bonded
+        )));
+    }
+
+    // Entries released last are rebonded first
+    store.unbonding.sort_by_key(|entry| entry.release_height);
+    let mut left = amount;
+    while let Some(entry) = store.unbonding.last_mut() {
+        if entry.amount > left {
+            entry.amount = entry.amount.sub(left)?;
+            break;
+        }
+        left = left.sub(entry.amount)?;
+        store.unbonding.pop();
+    }
+    store.un_bonded = store.un_bonded.sub(amount)?;
+    staking_storage(&mut deps.storage).save(sender_canonical.as_slice(), &store)?;
+
+    state.total_unbonding = state.total_unbonding.sub(amount)?;
+    bond(
+        &mut deps.storage,
+        &mut state,
+        &sender_canonical,
+        amount,
+        env.block.height,
+    )?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            LogAttribute {
+                key: "action".to_string(),
+                value: "rebond lota".to_string(),
+            },
+            LogAttribute {
+                key: "from".to_string(),
+                value: env.message.sender.to_string(),
+            },
+            LogAttribute {
+                key: "amount".to_string(),
+                value: amount.to_string(),
+            },
+        ],
+        data: None,
+    })
+}
+
+pub fn handle_claim_unstake<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> StdResult<HandleResponse> {
+    let mut state = config(&mut deps.storage).load()?;
+
+    if active_pause(&state, env.block.height).claim_unstake {
+        return Err(StdError::generic_err(
+            "Contract deactivated for update or/and preventing security issue",
+        ));
+    }
+
+    if !env.message.sent_funds.is_empty() {
+        return Err(StdError::generic_err("Do not send funds"));
+    }
+
+    let sender_canonical = deps.api.canonical_address(&env.message.sender)?;
+    let mut store = staking_storage(&mut deps.storage).load(sender_canonical.as_slice())?;
+
+    let (released, pending): (Vec<UnbondingInfo>, Vec<UnbondingInfo>) = store
+        .unbonding
+        .iter()
+        .cloned()
+        .partition(|entry| entry.release_height <= env.block.height);
+    if released.is_empty() {
+        if let Some(next_release) = pending.iter().map(|entry| entry.release_height).min() {
+            return Err(StdError::generic_err(format!(
+                "Your unBonded token will be released at block {}",
+                next_release
+            )));
+        }
+        return Err(StdError::generic_err("No amount available"))
+    }
+    let mut amount = Uint128::zero();
+    for entry in released {
+        amount = amount.add(entry.amount);
+    }
+    // Prepare msg to send
+    let msg = Cw20ExecuteMsg::Transfer {
+        recipient: env.message.sender.clone(),
+        amount,
+    };
+    // Convert state address of loterra cw-20
+    let lottera_human = deps
+        .api
+        .human_address(&state.address_cw20_loterra_smart_contract.clone())?;
+    // Prepare the message
+    let res = encode_msg_execute(msg, lottera_human)?;
+
+    store.un_bonded = store.un_bonded.sub(amount)?;
+    store.unbonding = pending;
+    staking_storage(&mut deps.storage).save(sender_canonical.as_slice(), &store)?;
+
+    state.total_unbonding = state.total_unbonding.sub(amount)?;
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse {
+        messages: vec![res],
+        log: vec![
+            LogAttribute {
+                key: "action".to_string(),
+                value: "claim unstake".to_string(),
+            },
+            LogAttribute {
+                key: "from".to_string(),
+                value: env.contract.address.to_string(),
+            },
+            LogAttribute {
+                key: "to".to_string(),
+                value: env.message.sender.to_string(),
+            },
+            LogAttribute {
+                key: "amount".to_string(),
+                value: amount.to_string(),
+            },
+        ],
+        data: None,
+    })
+}
+
+pub fn handle_emergency_withdraw<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> StdResult<HandleResponse> {
+    let mut state = config(&mut deps.storage).load()?;
+    lapse_pause(&mut state, env.block.height);
+    let locked_since = match state.locked_since {
+        Some(locked_since) if state.pause.blocks_withdrawal() => locked_since,
+        _ => return Err(StdError::generic_err("Emergency withdrawal not active")),
+    };
+    let withdrawal_height = locked_since + state.emergency_withdraw_delay;
+    if env.block.height < withdrawal_height {
+        return Err(StdError::generic_err(format!(
+            "Emergency withdrawal available from block {}",
+            withdrawal_height
+        )));
+    }
+    if !env.message.sent_funds.is_empty() {
+        return Err(StdError::generic_err("Do not send funds"));
+    }
+
+    let sender_canonical = deps.api.canonical_address(&env.message.sender)?;
+    let mut store = staking_storage(&mut deps.storage).load(sender_canonical.as_slice())?;
+    advance(&mut deps.storage, &mut state, env.block.height)?;
+    settle(&mut deps.storage, &state, &mut store)?;
+
+    // Locks and the unBonded period are ignored, the holder leaves entirely
+    if let Some(lock) = &store.lock {
+        remove_lock_expiry(&mut deps.storage, lock)?;
+    }
+    let warming = warming_amount(&store);
+    cool_down(&mut deps.storage, &mut state, &mut store, warming)?;
+    state.total_weighted = state.total_weighted.sub(weighted_bonded(&store))?;
+    state.total_bonded = state.total_bonded.sub(store.bonded)?;
+    state.total_unbonding = state.total_unbonding.sub(store.un_bonded)?;
+    checkpoint(
+        &mut deps.storage,
+        &state,
+        &sender_canonical,
+        Uint128::zero(),
+        env.block.height,
+    )?;
+    reindex_bonded(
+        &mut deps.storage,
+        &sender_canonical,
+        store.bonded,
+        Uint128::zero(),
+    )?;
+    staking_storage(&mut deps.storage).remove(sender_canonical.as_slice());
+    config(&mut deps.storage).save(&state)?;
+
+    let amount = store.bonded.add(store.un_bonded);
+    let lottera_human = deps
+        .api
+        .human_address(&state.address_cw20_loterra_smart_contract)?;
+    let mut messages = vec![];
+    if !amount.is_zero() {
+        let msg = Cw20ExecuteMsg::Transfer {
+            recipient: env.message.sender.clone(),
+            amount,
+        };
+        messages.push(encode_msg_execute(msg, lottera_human)?);
+    }
+    if !store.available.is_empty() {
+        messages.push(
+            BankMsg::Send {
+                from_address: env.contract.address.clone(),
+                to_address: env.message.sender.clone(),
+                amount: store.available.clone(),
+            }
+            .into(),
+        );
+    }
+    for balance in store.available_tokens.iter() {
+        let msg = Cw20ExecuteMsg::Transfer {
+            recipient: env.message.sender.clone(),
+            amount: balance.amount,
+        };
+        messages.push(encode_msg_execute(
+            msg,
+            deps.api.human_address(&balance.token)?,
+        )?);
+    }
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![
+            LogAttribute {
+                key: "action".to_string(),
+                value: "emergency withdraw".to_string(),
+            },
+            LogAttribute {
+                key: "to".to_string(),
+                value: env.message.sender.to_string(),
+            },
+            LogAttribute {
+                key: "amount".to_string(),
+                value: amount.to_string(),
+            },
+            LogAttribute {
+                key: "rewards".to_string(),
+                value: coins_to_string(&store.available),
+            },
+        ],
+        data: None,
+    })
+}
+
+pub fn handle_claim_reward<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> StdResult<HandleResponse> {
+    let mut state = config(&mut deps.storage).load()?;
+
+    if active_pause(&state, env.block.height).claim_reward {
+        return Err(StdError::generic_err(
+            "Contract deactivated for update or/and preventing security issue",
+        ));
+    }
+
+    if !env.message.sent_funds.is_empty() {
+        return Err(StdError::generic_err("Do not send funds"));
+    }
+
+    let sender_canonical = deps.api.canonical_address(&env.message.sender)?;
+    let mut store = staking_storage(&mut deps.storage).load(sender_canonical.as_slice())?;
+    advance(&mut deps.storage, &mut state, env.block.height)?;
+    settle(&mut deps.storage, &state, &mut store)?;
+    config(&mut deps.storage).save(&state)?;
+
+    if store.available.is_empty() && store.available_tokens.is_empty() {
+        return Err(StdError::generic_err("No rewards available"));
+    }
+
+    for coin in store.available.iter() {
+        let contract_balance = deps
+            .querier
+            .query_balance(env.contract.address.clone(), &coin.denom)?;
+        if contract_balance.amount < coin.amount {
+            return Err(StdError::generic_err("Contract balance too low"));
+        }
+    }
+
+    let mut messages = vec![];
+    let claimed = store.available;
+    if !claimed.is_empty() {
+        messages.push(
+            BankMsg::Send {
+                from_address: env.contract.address.clone(),
+                to_address: env.message.sender.clone(),
+                amount: claimed.clone(),
+            }
+            .into(),
+        );
+    }
+    let mut claimed_log = coins_to_string(&claimed);
+    for balance in store.available_tokens.iter() {
+        let token_human = deps.api.human_address(&balance.token)?;
+        let msg = Cw20ExecuteMsg::Transfer {
+            recipient: env.message.sender.clone(),
+            amount: balance.amount,
+        };
+        messages.push(encode_msg_execute(msg, token_human.clone())?);
+        if !claimed_log.is_empty() {
+            claimed_log.push(',');
+        }
+        claimed_log.push_str(&format!("{}{}", balance.amount, token_human));
+    }
+
+    store.available = vec![];
+    store.available_tokens = vec![];
+    staking_storage(&mut deps.storage).save(sender_canonical.as_slice(), &store)?;
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![
+            LogAttribute {
+                key: "action".to_string(),
+                value: "claim reward".to_string(),
+            },
+            LogAttribute {
+                key: "from".to_string(),
+                value: env.contract.address.to_string(),
+            },
+            LogAttribute {
+                key: "to".to_string(),
+                value: env.message.sender.to_string(),
+            },
+            LogAttribute {
+                key: "amount".to_string(),
+                value: claimed_log,
+            },
+        ],
+        data: None,
+    })
+}
+
+pub fn handle_payout_reward<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> StdResult<HandleResponse> {
+    let mut state = config(&mut deps.storage).load()?;
+    if active_pause(&state, env.block.height).payout {
+        return Err(StdError::generic_err(
+            "Contract deactivated for update or/and preventing security issue",
+        ));
+    }
+
+    if env.message.sent_funds.is_empty() {
+        return Err(StdError::generic_err(
+            "You need to send funds for share holders",
+        ));
+    }
+    check_reward_denoms(&state, &env.message.sent_funds)?;
+    // Batches matured since the last update join total_weighted first
+    advance(&mut deps.storage, &mut state, env.block.height)?;
+
+    // Only the indexes are updated, holders settle their share lazily
+    let mut refunds: Vec<Coin> = vec![];
+    let total_weighted = state.total_weighted;
+    for coin in env.message.sent_funds.iter() {
+        // Nobody earns rewards, the whole payout is carried or refunded like a remainder
+        if total_weighted.is_zero() {
+            if state.carry_reward_remainder {
+                add_coin(&mut state.reward_carry, &coin.denom, coin.amount);
+            } else {
+                add_coin(&mut refunds, &coin.denom, coin.amount);
+            }
+            continue;
+        }
+        // Remainder carried from previous payouts joins this one
+        let mut amount = coin.amount;
+        if let Some(pos) = state
+            .reward_carry
+            .iter()
+            .position(|c| c.denom == coin.denom)
+        {
+            amount = amount.add(state.reward_carry.remove(pos).amount);
+        }
+        let reward = state
+            .reward_indexes
+            .iter_mut()
+            .find(|r| r.denom == coin.denom)
+            .unwrap();
+        // Whole units the index precision can't represent
+        let remainder = distribute_reward(
+            total_weighted,
+            amount,
+            &mut reward.index,
+            &mut reward.remainder,
+        );
+        if state.carry_reward_remainder {
+            add_coin(&mut state.reward_carry, &coin.denom, remainder);
+        } else {
+            add_coin(&mut refunds, &coin.denom, remainder);
+        }
+    }
+    config(&mut deps.storage).save(&state)?;
+
+    let mut messages = vec![];
+    if !refunds.is_empty() {
+        messages.push(
+            BankMsg::Send {
+                from_address: env.contract.address.clone(),
+                to_address: env.message.sender.clone(),
+                amount: refunds.clone(),
+            }
+            .into(),
+        );
+    }
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![
+            LogAttribute {
+                key: "action".to_string(),
+                value: "payout reward".to_string(),
+            },
+            LogAttribute {
+                key: "amount".to_string(),
+                value: coins_to_string(&env.message.sent_funds),
+            },
+            LogAttribute {
+                key: "refund".to_string(),
+                value: coins_to_string(&refunds),
+            },
+            LogAttribute {
+                key: "carry".to_string(),
+                value: coins_to_string(&state.reward_carry),
+            },
+        ],
+        data: None,
+    })
+}
+
+pub fn handle_payout_reward_stream<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    start_height: u64,
+    end_height: u64,
+) -> StdResult<HandleResponse> {
+    let mut state = config(&mut deps.storage).load()?;
+    if active_pause(&state, env.block.height).payout {
+        return Err(StdError::generic_err(
+            "Contract deactivated for update or/and preventing security issue",
+        ));
+    }
+
+    if env.message.sent_funds.is_empty() {
+        return Err(StdError::generic_err(
+            "You need to send funds for share holders",
+        ));
+    }
+    let payer = deps.api.canonical_address(&env.message.sender)?;
+    if state.admin != payer && !state.stream_payers.contains(&payer) {
+        return Err(StdError::Unauthorized { backtrace: None });
+    }
+    check_reward_denoms(&state, &env.message.sent_funds)?;
+    if start_height < env.block.height {
+        return Err(StdError::generic_err("Stream can't start in the past"));
+    }
+    if end_height <= start_height {
+        return Err(StdError::generic_err(
+            "End height must be greater than start height",
+        ));
+    }
+
+    advance(&mut deps.storage, &mut state, env.block.height)?;
+    let running = state
+        .reward_streams
+        .iter()
+        .filter(|stream| stream.payer == payer)
+        .count();
+    if running + env.message.sent_funds.len() > MAX_REWARD_STREAMS {
+        return Err(StdError::generic_err(format!(
+            "Too many reward streams (max {})",
+            MAX_REWARD_STREAMS
+        )));
+    }
+    for coin in env.message.sent_funds.iter() {
+        state.reward_streams.push(RewardStream {
+            payer: payer.clone(),
+            denom: coin.denom.clone(),
+            amount: coin.amount,
+            released: Uint128::zero(),
+            start_height,
+            end_height,
+        });
+    }
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            LogAttribute {
+                key: "action".to_string(),
+                value: "payout reward stream".to_string(),
+            },
+            LogAttribute {
+                key: "amount".to_string(),
+                value: coins_to_string(&env.message.sent_funds),
+            },
+            LogAttribute {
+                key: "start_height".to_string(),
+                value: start_height.to_string(),
+            },
+            LogAttribute {
+                key: "end_height".to_string(),
+                value: end_height.to_string(),
+            },
+        ],
+        data: None,
+    })
+}
+
+pub fn query<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    msg: QueryMsg,
+) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::Config {} => to_binary(&query_config(deps)?),
+        QueryMsg::GetHolder { address } => to_binary(&query_holder(deps, address)?),
+        QueryMsg::GetAllBonded {} => to_binary(&query_all_bonded(deps)?),
+        QueryMsg::RewardStreams {} => to_binary(&query_reward_streams(deps)?),
+        QueryMsg::PendingAdminActions {} => to_binary(&query_pending_admin_actions(deps)?),
+        QueryMsg::GetHolders {
+            start_after,
+            limit,
+            order,
+        } => to_binary(&query_holders(deps, start_after, limit, order)?),
+        QueryMsg::EmergencyStatus {} => to_binary(&query_emergency_status(deps)?),
+        QueryMsg::PauseAt { height } => to_binary(&query_pause_at(deps, height)?),
+        QueryMsg::VotingPowerAt { address, height } => {
+            to_binary(&query_voting_power_at(deps, address, height)?)
+        }
+        QueryMsg::TotalVotingPowerAt { height } => {
+            to_binary(&query_total_voting_power_at(deps, height)?)
+        }
+        QueryMsg::Proposal { proposal_id } => to_binary(&query_proposal(deps, proposal_id)?),
+        QueryMsg::TopStakers { limit, start_after } => {
+            to_binary(&query_top_stakers(deps, limit, start_after)?)
+        }
+        QueryMsg::Vote {
+            proposal_id,
+            address,
+        } => to_binary(&query_vote(deps, proposal_id, address)?),
+    }
+}
+
+fn query_config<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<ConfigResponse> {
+    let state = config_read(&deps.storage).load()?;
+    Ok(state)
+}
+
+fn query_holder<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    address: HumanAddr,
+) -> StdResult<GetHolderResponse> {
+    let state = config_read(&deps.storage).load()?;
+    let address_to_canonical = deps.api.canonical_address(&address)?;
+    let mut store =
+        match staking_storage_read(&deps.storage).may_load(address_to_canonical.as_slice())? {
+            Some(stake) => stake,
+            None => {
+                return Err(StdError::NotFound {
+                    kind: "not found".to_string(),
+                    backtrace: None,
+                })
+            }
+        };
+    // Include rewards not yet settled in storage
+    settle_matured(&deps.storage, &state, &mut store)?;
+
+    let mut streaming = vec![];
+    let weighted = weighted_bonded(&store);
+    let eligible = store.bonded.sub(warming_amount(&store))?;
+    if !state.total_weighted.is_zero() {
+        for stream in state.reward_streams.iter() {
+            let remaining = stream.amount.sub(stream.released)?;
+            let share = remaining.multiply_ratio(weighted, state.total_weighted);
+            add_coin(&mut streaming, &stream.denom, share);
+        }
+    }
+
+    let mut available_tokens = vec![];
+    for balance in store.available_tokens {
+        available_tokens.push(TokenBalanceResponse {
+            token: deps.api.human_address(&balance.token)?,
+            amount: balance.amount,
+        });
+    }
+
+    Ok(GetHolderResponse {
+        address,
+        bonded: store.bonded,
+        un_bonded: store.un_bonded,
+        available: store.available,
+        available_tokens,
+        streaming,
+        period: store
+            .unbonding
+            .iter()
+            .map(|entry| entry.release_height)
+            .min()
+            .unwrap_or(0),
+        unbonding: store.unbonding,
+        lock: store.lock,
+        weighted,
+        eligible,
+        warming: store.warming,
+    })
+}
+fn query_holders<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    start_after: Option<HumanAddr>,
+    limit: Option<u32>,
+    order: Option<OrderBy>,
+) -> StdResult<GetHoldersResponse> {
+    let state = config_read(&deps.storage).load()?;
+    let limit = limit
+        .unwrap_or(DEFAULT_HOLDERS_LIMIT)
+        .min(MAX_HOLDERS_LIMIT) as usize;
+    let start_after = match start_after {
+        Some(address) => Some(deps.api.canonical_address(&address)?),
+        None => None,
+    };
+    let holders = staking_storage_read(&deps.storage);
+    let iter = match order.unwrap_or(OrderBy::Asc) {
+        OrderBy::Asc => {
+            // Smallest key above start_after
+            let start = start_after.map(|address| {
+                let mut key = address.as_slice().to_vec();
+                key.push(0);
+                key
+            });
+            holders.range(start.as_deref(), None, Order::Ascending)
+        }
+        OrderBy::Desc => holders.range(
+            None,
+            start_after.as_ref().map(|address| address.as_slice()),
+            Order::Descending,
+        ),
+    };
+
+    let holders = iter
+        .take(limit)
+        .map(|item| {
+            let (key, mut store) = item?;
+            // Include rewards not yet settled in storage
+            settle_matured(&deps.storage, &state, &mut store)?;
+            Ok(GetBondedResponse {
+                address: deps.api.human_address(&CanonicalAddr::from(key))?,
+                bonded: store.bonded,
+                un_bonded: store.un_bonded,
+                available: store.available,
+                period: store
+                    .unbonding
+                    .iter()
+                    .map(|entry| entry.release_height)
+                    .min()
+                    .unwrap_or(0),
+            })
+        })
+        .collect::<StdResult<Vec<GetBondedResponse>>>()?;
+    Ok(GetHoldersResponse { holders })
+}
+
+fn query_top_stakers<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    limit: Option<u32>,
+    start_after: Option<HumanAddr>,
+) -> StdResult<TopStakersResponse> {
+    let state = config_read(&deps.storage).load()?;
+    let limit = limit
+        .unwrap_or(DEFAULT_HOLDERS_LIMIT)
+        .min(MAX_HOLDERS_LIMIT) as usize;
+    let end = match start_after {
+        Some(address) => {
+            let address = deps.api.canonical_address(&address)?;
+            let stake = staking_storage_read(&deps.storage).load(address.as_slice())?;
+            Some(bonded_index_key(stake.bonded, &address))
+        }
+        None => None,
+    };
+
+    let stakers = bonded_index_storage_read(&deps.storage)
+        .range(None, end.as_deref(), Order::Descending)
+        .take(limit)
+        .map(|item| {
+            let (key, address) = item?;
+            let mut bonded = [0u8; 16];
+            bonded.copy_from_slice(&key[..16]);
+            let bonded = Uint128(u128::from_be_bytes(bonded));
+            Ok(TopStakerResponse {
+                address: deps.api.human_address(&address)?,
+                bonded,
+                share: Decimal::from_ratio(bonded, state.total_bonded),
+            })
+        })
+        .collect::<StdResult<Vec<TopStakerResponse>>>()?;
+    Ok(TopStakersResponse { stakers })
+}
+
+fn query_all_bonded<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<GetAllBondedResponse> {
+    let state = config_read(&deps.storage).load()?;
+    Ok(GetAllBondedResponse {
+        total_bonded: state.total_bonded,
+        total_unbonding: state.total_unbonding,
+    })
+}
+
+fn query_reward_streams<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<RewardStreamsResponse> {
+    let state = config_read(&deps.storage).load()?;
+    Ok(RewardStreamsResponse {
+        streams: state.reward_streams,
+    })
+}
+
+fn query_pending_admin_actions<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<PendingAdminActionsResponse> {
+    let actions = admin_action_storage_read(&deps.storage)
+        .range(None, None, Order::Ascending)
+        .map(|item| item.map(|(_, action)| action))
+        .collect::<StdResult<Vec<AdminAction>>>()?;
+    Ok(PendingAdminActionsResponse { actions })
+}
+
+fn query_emergency_status<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<EmergencyStatusResponse> {
+    let state = config_read(&deps.storage).load()?;
+    let locked_since = match state.lock_lapsed_at {
+        Some(_) => None,
+        None => state.locked_since,
+    };
+    let withdrawal_height = match (locked_since, state.pause_expiry) {
+        // The pause lapses before withdrawals could be forced
+        (Some(since), Some(expiry)) if expiry <= since + state.emergency_withdraw_delay => None,
+        (Some(since), _) => Some(since + state.emergency_withdraw_delay),
+        (None, _) => None,
+    };
+    Ok(EmergencyStatusResponse {
+        locked_since,
+        emergency_withdraw_delay: state.emergency_withdraw_delay,
+        withdrawal_height,
+    })
+}
+
+fn query_pause_at<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    height: u64,
+) -> StdResult<PauseResponse> {
+    let state = config_read(&deps.storage).load()?;
+    Ok(PauseResponse {
+        pause: active_pause(&state, height),
+        pause_expiry: state.pause_expiry,
+    })
+}
+
+/// Value of the last checkpoint at or before height, zero before the first one
+fn power_at<S: Storage>(
+    checkpoints: ReadonlyBucket<'_, S, Uint128>,
+    height: u64,
+) -> StdResult<Uint128> {
+    let end = height.saturating_add(1).to_be_bytes();
+    match checkpoints
+        .range(None, Some(&end), Order::Descending)
+        .next()
+    {
+        Some(item) => Ok(item?.1),
+        None => Ok(Uint128::zero()),
+    }
+}
+
+fn query_voting_power_at<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    address: HumanAddr,
+    height: u64,
+) -> StdResult<VotingPowerResponse> {
+    let address = deps.api.canonical_address(&address)?;
+    Ok(VotingPowerResponse {
+        height,
+        power: power_at(voting_power_storage_read(&deps.storage, &address), height)?,
+    })
+}
+
+fn query_total_voting_power_at<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    height: u64,
+) -> StdResult<VotingPowerResponse> {
+    Ok(VotingPowerResponse {
+        height,
+        power: power_at(total_voting_power_storage_read(&deps.storage), height)?,
+    })
+}
+
+fn query_proposal<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    proposal_id: u64,
+) -> StdResult<ProposalResponse> {
+    load_proposal(&deps.storage, proposal_id)
+}
+
+fn query_vote<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    proposal_id: u64,
+    address: HumanAddr,
+) -> StdResult<VoteResponse> {
+    let voter = deps.api.canonical_address(&address)?;
+    Ok(VoteResponse {
+        vote: vote_storage_read(&deps.storage, proposal_id).may_load(voter.as_slice())?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{LegacyState, LockTier, CONFIG_KEY};
+    use cosmwasm_std::coins;
+    use cosmwasm_std::testing::{mock_dependencies, mock_env};
+    use cosmwasm_std::StdError::GenericErr;
+    use cosmwasm_storage::{bucket, singleton};
+
+    struct BeforeAll {
+        default_length: usize,
+        default_sender: HumanAddr,
+        default_sender_two: HumanAddr,
+        default_sender_owner: HumanAddr,
+        default_contract_address: HumanAddr,
+        default_contract_address_two: HumanAddr,
+    }
+    fn before_all() -> BeforeAll {
+        BeforeAll {
+            default_length: HumanAddr::from("terra1q88h7ewu6h3am4mxxeqhu3srt7zw4z5s20qu3k").len(),
+            default_sender: HumanAddr::from("terra1q88h7ewu6h3am4mxxeqhu3srt7zw4z5s20q007"),
+            default_sender_two: HumanAddr::from("terra1q88h7ewu6h3am4mxxeqhu3srt7zw4z5s20q008"),
+            default_sender_owner: HumanAddr::from("terra1q88h7ewu6h3am4mxxeqhu3srt7zw4z5s20qu3k"),
+            default_contract_address: HumanAddr::from(
+                "terra1q88h7ewu6h3am4mxxeqhu3srt7zw4z5s20LOTA",
+            ),
+            default_contract_address_two: HumanAddr::from(
+                "terra1q88h7ewu6h3am4mxxeqhu3srt7zw4z5LOTERRA",
+            ),
+        }
+    }
+
+    fn default_init<S: Storage, A: Api, Q: Querier>(mut deps: &mut Extern<S, A, Q>) {
+        let before_all = before_all();
+        let init_msg = InitMsg {
+            address_cw20_loterra_smart_contract: before_all.default_contract_address,
+            unbonded_period: 100,
+            reward_denoms: vec!["uusd".to_string()],
+            reward_tokens: vec![],
+            max_unbonding_entries: 3,
+            carry_reward_remainder: false,
+            lock_tiers: vec![
+                LockTier {
+                    duration: 1_000,
+                    weight: Decimal::percent(150),
+                },
+                LockTier {
+                    duration: 5_000,
+                    weight: Decimal::percent(200),
+                },
+            ],
+            min_bonding_age: 0,
+            timelock_delay: 0,
+            emergency_withdraw_delay: 1_000,
+            max_pause_duration: 50_000,
+            governance: GovernanceConfig {
+                proposal_min_stake: Uint128(1_000),
+                quorum: Decimal::percent(30),
+                vote_threshold: Decimal::percent(50),
+                voting_period: 100,
+            },
+        };
+        let res = init(
+            &mut deps,
+            mock_env("terra1q88h7ewu6h3am4mxxeqhu3srt7zw4z5s20qu3k", &[]),
+            init_msg,
+        )
+        .unwrap();
+        assert_eq!(0, res.messages.len());
+    }
+
+    fn assert_totals_consistent<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>) {
+        let state = config_read(&deps.storage).load().unwrap();
+        let mut total_bonded = Uint128::zero();
+        let mut total_weighted = Uint128::zero();
+        let mut total_warming = Uint128::zero();
+        let mut total_unbonding = Uint128::zero();
+        for item in staking_storage_read(&deps.storage).range(None, None, Order::Ascending) {
+            let (_, mut stake) = item.unwrap();
+            settle_matured(&deps.storage, &state, &mut stake).unwrap();
+            total_bonded = total_bonded.add(stake.bonded);
+            total_weighted = total_weighted.add(weighted_bonded(&stake));
+            total_warming = total_warming.add(warming_amount(&stake));
+            total_unbonding = total_unbonding.add(stake.un_bonded);
+        }
+        assert_eq!(state.total_bonded, total_bonded);
+        assert_eq!(state.total_weighted, total_weighted);
+        assert_eq!(state.total_warming, total_warming);
+        assert_eq!(state.total_unbonding, total_unbonding);
+    }
+
+    #[test]
+    fn proper_initialization() {
+        let before_all = before_all();
+        let mut deps = mock_dependencies(before_all.default_length, &[]);
+        let env = mock_env("creator", &coins(1000, "earth"));
+        default_init(&mut deps);
+    }
+
+    #[test]
+    fn init_refuse_invalid_parameters() {
+        let before_all = before_all();
+        let init_msg = InitMsg {
+            address_cw20_loterra_smart_contract: before_all.default_contract_address,
+            unbonded_period: 100,
+            reward_denoms: vec!["uusd".to_string()],
+            reward_tokens: vec![],
+            max_unbonding_entries: 3,
+            carry_reward_remainder: false,
+            lock_tiers: vec![],
+            min_bonding_age: 0,
+            timelock_delay: 0,
+            emergency_withdraw_delay: 1_000,
+            max_pause_duration: 50_000,
+            governance: GovernanceConfig {
+                proposal_min_stake: Uint128(1_000),
+                quorum: Decimal::percent(30),
+                vote_threshold: Decimal::percent(50),
+                voting_period: 100,
+            },
+        };
+        let mut zero_entries = init_msg.clone();
+        zero_entries.max_unbonding_entries = 0;
+        let mut zero_delay = init_msg;
+        zero_delay.emergency_withdraw_delay = 0;
+        let cases = [
+            (zero_entries, "Max unBonding entries must be at least 1"),
+            (zero_delay, "Emergency withdraw delay must be at least 1"),
+        ];
+        for (init_msg, error) in cases.iter() {
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            let env = mock_env(before_all.default_sender_owner.clone(), &[]);
+            let res = init(&mut deps, env, init_msg.clone());
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, *error);
+                }
+                _ => panic!("Unexpected error"),
+            }
+        }
+    }
+    mod migrate {
+        use super::*;
+        // migrate
+
+        fn legacy_msg() -> LegacyMigrateMsg {
+            LegacyMigrateMsg {
+                reward_tokens: vec![],
+                max_unbonding_entries: 3,
+                carry_reward_remainder: false,
+                lock_tiers: vec![],
+                min_bonding_age: 0,
+                timelock_delay: 0,
+                emergency_withdraw_delay: 1_000,
+                max_pause_duration: 50_000,
+                governance: GovernanceConfig {
+                    proposal_min_stake: Uint128(1_000),
+                    quorum: Decimal::percent(30),
+                    vote_threshold: Decimal::percent(50),
+                    voting_period: 100,
+                },
+            }
+        }
+
+        #[test]
+        fn current_layout() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(
+                before_all.default_length,
+                &[Coin {
+                    denom: "uusd".to_string(),
+                    amount: Uint128(10_000),
+                }],
+            );
+            default_init(&mut deps);
+            let env = mock_env(before_all.default_sender.clone(), &[]);
+            let msg = HandleMsg::Stake {
+                amount: Uint128(2_000),
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
+            let msg = HandleMsg::UnStake {
+                amount: Uint128(500),
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
+            let payout_env = mock_env(
+                before_all.default_sender_owner.clone(),
+                &[Coin {
+                    denom: "uusd".to_string(),
+                    amount: Uint128(1_000),
+                }],
+            );
+            handle(&mut deps, payout_env, HandleMsg::PayoutReward {}).unwrap();
+            let state = config_read(&deps.storage).load().unwrap();
+            let holder = query_holder(&deps, before_all.default_sender.clone()).unwrap();
+
+            let res = migrate(&mut deps, env.clone(), MigrateMsg { legacy: None }).unwrap();
+            assert_eq!(res.log[1].value, CONTRACT_VERSION);
+            assert_eq!(config_read(&deps.storage).load().unwrap(), state);
+            assert_eq!(
+                query_holder(&deps, before_all.default_sender.clone()).unwrap(),
+                holder
+            );
+            let info = contract_info_read(&deps.storage).load().unwrap();
+            assert_eq!(info.contract, CONTRACT_NAME);
+            assert_eq!(info.version, CONTRACT_VERSION);
+
+            // The holder keeps working after the migration
+            let env = mock_env(before_all.default_sender.clone(), &[]);
+            handle(&mut deps, env, HandleMsg::ClaimReward {}).unwrap();
+            assert_totals_consistent(&deps);
+        }
+
+        #[test]
+        fn refuse_foreign_contract() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            contract_info(&mut deps.storage)
+                .save(&ContractInfo {
+                    contract: "crates.io:cw20-base".to_string(),
+                    version: "0.1.0".to_string(),
+                })
+                .unwrap();
+            let env = mock_env(before_all.default_sender_owner.clone(), &[]);
+            let res = migrate(&mut deps, env, MigrateMsg { legacy: None });
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, "Can't migrate from contract crates.io:cw20-base");
+                }
+                _ => panic!("Unexpected error"),
+            }
+        }
+
+        #[test]
+        fn refuse_downgrade() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            contract_info(&mut deps.storage)
+                .save(&ContractInfo {
+                    contract: CONTRACT_NAME.to_string(),
+                    version: "99.0.0".to_string(),
+                })
+                .unwrap();
+            let env = mock_env(before_all.default_sender_owner.clone(), &[]);
+            let res = migrate(&mut deps, env, MigrateMsg { legacy: None });
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(
+                        msg,
+                        format!(
+                            "Can't downgrade from version 99.0.0 to {}",
+                            CONTRACT_VERSION
+                        )
+                    );
+                }
+                _ => panic!("Unexpected error"),
+            }
+        }
+
+        #[test]
+        fn legacy_layout() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            let admin = deps
+                .api
+                .canonical_address(&before_all.default_sender_owner)
+                .unwrap();
+            let legacy = LegacyState {
+                admin: admin.clone(),
+                address_cw20_loterra_smart_contract: deps
+                    .api
+                    .canonical_address(&before_all.default_contract_address)
+                    .unwrap(),
+                unbonded_period: 100,
+                denom_reward: "uusd".to_string(),
+                safe_lock: true,
+            };
+            singleton(&mut deps.storage, CONFIG_KEY)
+                .save(&legacy)
+                .unwrap();
+            let holder = deps
+                .api
+                .canonical_address(&before_all.default_sender)
+                .unwrap();
+            bucket(b"staking", &mut deps.storage)
+                .save(
+                    holder.as_slice(),
+                    &LegacyStakingInfo {
+                        bonded: Uint128(2_000),
+                        un_bonded: Uint128(500),
+                        period: 12_400,
+                        available: Uint128(300),
+                    },
+                )
+                .unwrap();
+
+            let env = mock_env(before_all.default_sender_owner.clone(), &[]);
+            let res = migrate(&mut deps, env.clone(), MigrateMsg { legacy: None });
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(
+                        msg,
+                        "Migrating from the first layout needs the legacy parameters"
+                    );
+                }
+                _ => panic!("Unexpected error"),
+            }
+            let mut params = legacy_msg();
+            params.reward_tokens = vec![before_all.default_contract_address_two.clone()];
+            let msg = MigrateMsg {
+                legacy: Some(params),
+            };
+            let res = migrate(&mut deps, env.clone(), msg).unwrap();
+            assert_eq!(res.log[1].value, "legacy");
+
+            let state = config_read(&deps.storage).load().unwrap();
+            assert_eq!(state.admin, admin);
+            assert_eq!(state.pause, PauseFlags::all());
+            assert_eq!(state.locked_since, Some(env.block.height));
+            assert_eq!(state.reward_indexes[0].denom, "uusd");
+            assert_eq!(
+                state.token_reward_indexes[0].token,
+                deps.api
+                    .canonical_address(&before_all.default_contract_address_two)
+                    .unwrap()
+            );
+            assert_eq!(state.total_bonded, Uint128(2_000));
+            assert_eq!(state.total_weighted, Uint128(2_000));
+            assert_eq!(state.total_unbonding, Uint128(500));
+            let power = query_voting_power_at(&deps, before_all.default_sender.clone(), u64::MAX);
+            assert_eq!(power.unwrap().power, Uint128(2_000));
+            let top = query_top_stakers(&deps, None, None).unwrap();
+            assert_eq!(top.stakers[0].address, before_all.default_sender);
+            assert_eq!(top.stakers[0].share, Decimal::one());
+            let holder = query_holder(&deps, before_all.default_sender.clone()).unwrap();
+            assert_eq!(holder.bonded, Uint128(2_000));
+            assert_eq!(
+                holder.unbonding,
+                vec![UnbondingInfo {
+                    amount: Uint128(500),
+                    release_height: 12_400,
+                }]
+            );
+            assert_eq!(
+                holder.available,
+                vec![Coin {
+                    denom: "uusd".to_string(),
+                    amount: Uint128(300),
+                }]
+            );
+            assert_totals_consistent(&deps);
+
+            // The store is versioned now
+            let msg = MigrateMsg {
+                legacy: Some(legacy_msg()),
+            };
+            migrate(&mut deps, env, msg).unwrap();
+            assert_eq!(config_read(&deps.storage).load().unwrap(), state);
+        }
+
+        #[test]
+        fn refuse_invalid_legacy_parameters() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            let legacy = LegacyState {
+                admin: deps
+                    .api
+                    .canonical_address(&before_all.default_sender_owner)
+                    .unwrap(),
+                address_cw20_loterra_smart_contract: deps
+                    .api
+                    .canonical_address(&before_all.default_contract_address)
+                    .unwrap(),
+                unbonded_period: 100,
+                denom_reward: "uusd".to_string(),
+                safe_lock: false,
+            };
+            singleton(&mut deps.storage, CONFIG_KEY)
+                .save(&legacy)
+                .unwrap();
+            let env = mock_env(before_all.default_sender_owner.clone(), &[]);
+            let mut zero_entries = legacy_msg();
+            zero_entries.max_unbonding_entries = 0;
+            let mut zero_delay = legacy_msg();
+            zero_delay.emergency_withdraw_delay = 0;
+            let cases = [
+                (zero_entries, "Max unBonding entries must be at least 1"),
+                (zero_delay, "Emergency withdraw delay must be at least 1"),
+            ];
+            for (legacy_msg, error) in cases.iter() {
+                let msg = MigrateMsg {
+                    legacy: Some(legacy_msg.clone()),
+                };
+                let res = migrate(&mut deps, env.clone(), msg);
+                match res {
+                    Err(GenericErr {
+                        msg,
+                        backtrace: None,
+                    }) => {
+                        assert_eq!(msg, *error);
+                    }
+                    _ => panic!("Unexpected error"),
+                }
+            }
+        }
+
+        #[test]
+        fn refuse_unknown_state() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            let env = mock_env(before_all.default_sender_owner.clone(), &[]);
+            let msg = MigrateMsg {
+                legacy: Some(legacy_msg()),
+            };
+            let res = migrate(&mut deps, env, msg);
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, "Unknown contract state");
+                }
+                _ => panic!("Unexpected error"),
+            }
+        }
+    }
+    mod safe_lock {
+        use super::*;
+        // handle_switch
+
+        #[test]
+        fn only_admin() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env = mock_env(before_all.default_sender_two, &[]);
+
+            let res = handle_safe_lock(&mut deps, env);
+            match res {
+                Err(StdError::Unauthorized { .. }) => {}
+                _ => panic!("Unexpected error"),
+            }
+        }
+        #[test]
+        fn success() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env = mock_env(before_all.default_sender_owner, &[]);
+
+            // Switch to Off
+            let res = handle_safe_lock(&mut deps, env.clone()).unwrap();
+            assert_eq!(res.messages.len(), 0);
+            let state = config(&mut deps.storage).load().unwrap();
+            assert_eq!(state.pause, PauseFlags::all());
+            // Switch to On
+            let res = handle_safe_lock(&mut deps, env).unwrap();
+            println!("{:?}", res);
+            let state = config(&mut deps.storage).load().unwrap();
+            assert_eq!(state.pause, PauseFlags::default());
+        }
+        #[test]
+        fn pause_flags() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let mut env = mock_env(before_all.default_sender.clone(), &[]);
+            let msg = HandleMsg::Stake {
+                amount: Uint128(1_000),
+            };
+            handle(&mut deps, env.clone(), msg.clone()).unwrap();
+            let unstake = HandleMsg::UnStake {
+                amount: Uint128(1_000),
+            };
+            handle(&mut deps, env.clone(), unstake).unwrap();
+
+            let flags = PauseFlags {
+                stake: true,
+                payout: true,
+                ..PauseFlags::default()
+            };
+            let set_flags = HandleMsg::SetPauseFlags {
+                flags: flags.clone(),
+            };
+            let res = handle(&mut deps, env.clone(), set_flags.clone());
+            match res {
+                Err(StdError::Unauthorized { .. }) => {}
+                _ => panic!("Unexpected error"),
+            }
+            let env_admin = mock_env(before_all.default_sender_owner.clone(), &[]);
+            handle(&mut deps, env_admin, set_flags).unwrap();
+            assert_eq!(query_config(&deps).unwrap().pause, flags);
+
+            let res = handle(&mut deps, env.clone(), msg);
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(
+                        msg,
+                        "Contract deactivated for update or/and preventing security issue"
+                    );
+                }
+                _ => panic!("Unexpected error"),
+            }
+            // Matured unBonded tokens can still be withdrawn
+            env.block.height += 100;
+            handle(&mut deps, env, HandleMsg::ClaimUnStaked {}).unwrap();
+        }
+    }
+    mod pause_expiry {
+        use super::*;
+        // handle_extend_pause
+
+        #[test]
+        fn pause_lapses() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let owner_env = mock_env(before_all.default_sender_owner.clone(), &[]);
+            handle(&mut deps, owner_env.clone(), HandleMsg::SafeLock {}).unwrap();
+            let state = query_config(&deps).unwrap();
+            assert_eq!(state.paused_since, Some(owner_env.block.height));
+            assert_eq!(state.pause_expiry, Some(owner_env.block.height + 50_000));
+
+            let mut env = mock_env(before_all.default_sender.clone(), &[]);
+            env.block.height += 49_999;
+            let msg = HandleMsg::Stake {
+                amount: Uint128(2_000),
+            };
+            let res = handle(&mut deps, env.clone(), msg.clone());
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(
+                        msg,
+                        "Contract deactivated for update or/and preventing security issue"
+                    );
+                }
+                _ => panic!("Unexpected error"),
+            }
+            env.block.height += 1;
+            // The stored flags stay set until the next handler, the query tells the lapse
+            assert_eq!(query_config(&deps).unwrap().pause, PauseFlags::all());
+            let pause = query_pause_at(&deps, env.block.height - 1).unwrap();
+            assert_eq!(pause.pause, PauseFlags::all());
+            let pause = query_pause_at(&deps, env.block.height).unwrap();
+            assert_eq!(pause.pause, PauseFlags::default());
+            assert_eq!(pause.pause_expiry, Some(env.block.height));
+            handle(&mut deps, env.clone(), msg).unwrap();
+
+            // SafeLock after the lapse pauses again instead of resuming
+            let start = owner_env.block.height;
+            let mut owner_env = owner_env;
+            owner_env.block.height = env.block.height;
+            handle(&mut deps, owner_env.clone(), HandleMsg::SafeLock {}).unwrap();
+            let state = config_read(&deps.storage).load().unwrap();
+            assert_eq!(state.pause, PauseFlags::all());
+            assert_eq!(state.paused_since, Some(env.block.height));
+            // Locked again right at the lapse, the withdrawal clock keeps running
+            assert_eq!(state.locked_since, Some(start));
+            assert_eq!(state.pause_expiry, Some(env.block.height + 50_000));
+        }
+
+        #[test]
+        fn extend_pause() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let mut owner_env = mock_env(before_all.default_sender_owner.clone(), &[]);
+            let res = handle(&mut deps, owner_env.clone(), HandleMsg::ExtendPause {});
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, "Contract is not paused");
+                }
+                _ => panic!("Unexpected error"),
+            }
+            let start = owner_env.block.height;
+            handle(&mut deps, owner_env.clone(), HandleMsg::SafeLock {}).unwrap();
+
+            let env = mock_env(before_all.default_sender.clone(), &[]);
+            let res = handle(&mut deps, env, HandleMsg::ExtendPause {});
+            match res {
+                Err(StdError::Unauthorized { .. }) => {}
+                _ => panic!("Unexpected error"),
+            }
+
+            owner_env.block.height += 40_000;
+            handle(&mut deps, owner_env.clone(), HandleMsg::ExtendPause {}).unwrap();
+            let state = config_read(&deps.storage).load().unwrap();
+            assert_eq!(state.paused_since, Some(start));
+            assert_eq!(state.pause_expiry, Some(start + 90_000));
+
+            let mut env = mock_env(before_all.default_sender.clone(), &[]);
+            env.block.height = start + 50_000;
+            let msg = HandleMsg::Stake {
+                amount: Uint128(2_000),
+            };
+            assert!(handle(&mut deps, env.clone(), msg.clone()).is_err());
+            env.block.height = start + 90_000;
+            handle(&mut deps, env, msg).unwrap();
+
+            // A lapsed pause can't be extended
+            owner_env.block.height = start + 90_000;
+            let res = handle(&mut deps, owner_env, HandleMsg::ExtendPause {});
+            assert!(res.is_err());
+        }
+
+        #[test]
+        fn resume_clears_expiry() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let owner_env = mock_env(before_all.default_sender_owner.clone(), &[]);
+            handle(&mut deps, owner_env.clone(), HandleMsg::SafeLock {}).unwrap();
+            handle(&mut deps, owner_env, HandleMsg::SafeLock {}).unwrap();
+            let state = config_read(&deps.storage).load().unwrap();
+            assert_eq!(state.paused_since, None);
+            assert_eq!(state.pause_expiry, None);
+        }
+    }
+    mod guardian {
+        use super::*;
+        // handle_set_guardian
+
+        fn set_guardian<S: Storage, A: Api, Q: Querier>(deps: &mut Extern<S, A, Q>) {
+            let before_all = before_all();
+            let env = mock_env(before_all.default_sender_owner, &[]);
+            let msg = HandleMsg::SetGuardian {
+                guardian: Some(before_all.default_sender_two),
+            };
+            handle(deps, env, msg).unwrap();
+        }
+
+        #[test]
+        fn only_admin() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env = mock_env(before_all.default_sender_two.clone(), &[]);
+            let msg = HandleMsg::SetGuardian {
+                guardian: Some(before_all.default_sender_two.clone()),
+            };
+            let res = handle(&mut deps, env.clone(), msg);
+            match res {
+                Err(StdError::Unauthorized { .. }) => {}
+                _ => panic!("Unexpected error"),
+            }
+            // Nobody but the admin can pause without a guardian
+            let res = handle(&mut deps, env, HandleMsg::SafeLock {});
+            match res {
+                Err(StdError::Unauthorized { .. }) => {}
+                _ => panic!("Unexpected error"),
+            }
+        }
+
+        #[test]
+        fn success() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            set_guardian(&mut deps);
+            let state = query_config(&deps).unwrap();
+            assert_eq!(
+                state.guardian,
+                Some(
+                    deps.api
+                        .canonical_address(&before_all.default_sender_two)
+                        .unwrap()
+                )
+            );
+
+            let env = mock_env(before_all.default_sender_owner.clone(), &[]);
+            let msg = HandleMsg::SetGuardian { guardian: None };
+            handle(&mut deps, env, msg).unwrap();
+            let state = config_read(&deps.storage).load().unwrap();
+            assert_eq!(state.guardian, None);
+        }
+
+        #[test]
+        fn guardian_can_only_pause() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            set_guardian(&mut deps);
+            let env = mock_env(before_all.default_sender_two.clone(), &[]);
+
+            let flags = PauseFlags {
+                stake: true,
+                ..PauseFlags::default()
+            };
+            let msg = HandleMsg::SetPauseFlags {
+                flags: flags.clone(),
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
+            let state = config_read(&deps.storage).load().unwrap();
+            assert_eq!(state.pause, flags);
+
+            // Resuming stake while pausing payout is refused
+            let msg = HandleMsg::SetPauseFlags {
+                flags: PauseFlags {
+                    payout: true,
+                    ..PauseFlags::default()
+                },
+            };
+            let res = handle(&mut deps, env.clone(), msg);
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, "Guardian can only pause");
+                }
+                _ => panic!("Unexpected error"),
+            }
+
+            handle(&mut deps, env.clone(), HandleMsg::SafeLock {}).unwrap();
+            let state = config_read(&deps.storage).load().unwrap();
+            assert_eq!(state.pause, PauseFlags::all());
+            // SafeLock would resume everything now
+            let res = handle(&mut deps, env.clone(), HandleMsg::SafeLock {});
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, "Guardian can only pause");
+                }
+                _ => panic!("Unexpected error"),
+            }
+
+            // The guardian can't touch anything else
+            let msg = HandleMsg::UpdateConfig {
+                unbonded_period: Some(1),
+                reward_denoms: None,
+                reward_tokens: None,
+                address_cw20_loterra_smart_contract: None,
+                max_unbonding_entries: None,
+                carry_reward_remainder: None,
+                lock_tiers: None,
+                min_bonding_age: None,
+                timelock_delay: None,
+                emergency_withdraw_delay: None,
+                max_pause_duration: None,
+                governance: None,
+            };
+            let res = handle(&mut deps, env, msg);
+            match res {
+                Err(StdError::Unauthorized { .. }) => {}
+                _ => panic!("Unexpected error"),
+            }
+
+            // The admin resumes
+            let env = mock_env(before_all.default_sender_owner.clone(), &[]);
+            handle(&mut deps, env, HandleMsg::SafeLock {}).unwrap();
+            let state = config_read(&deps.storage).load().unwrap();
+            assert_eq!(state.pause, PauseFlags::default());
+        }
+
+        #[test]
+        fn guardian_cooldown_after_lapse() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            set_guardian(&mut deps);
+            let mut env = mock_env(before_all.default_sender_two.clone(), &[]);
+            let start = env.block.height;
+            handle(&mut deps, env.clone(), HandleMsg::SafeLock {}).unwrap();
+
+            // Pausing again right at the lapse doesn't give a fresh expiry
+            env.block.height = start + 50_000;
+            let res = handle(&mut deps, env.clone(), HandleMsg::SafeLock {});
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(
+                        msg,
+                        "Pause lapsed, the guardian can pause again from block 112345"
+                    );
+                }
+                _ => panic!("Unexpected error"),
+            }
+            // Resuming the lapsed pause doesn't clear the cooldown
+            let msg = HandleMsg::SetPauseFlags {
+                flags: PauseFlags::default(),
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
+            let res = handle(&mut deps, env.clone(), HandleMsg::SafeLock {});
+            assert!(res.is_err());
+            let state = config_read(&deps.storage).load().unwrap();
+            assert_eq!(state.pause, PauseFlags::default());
+            assert_eq!(state.pause_expiry, Some(start + 50_000));
+
+            env.block.height = start + 100_000;
+            handle(&mut deps, env, HandleMsg::SafeLock {}).unwrap();
+            let state = config_read(&deps.storage).load().unwrap();
+            assert_eq!(state.pause, PauseFlags::all());
+            assert_eq!(state.pause_expiry, Some(start + 150_000));
+        }
+
+        #[test]
+        fn renounce_removes_guardian() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            set_guardian(&mut deps);
+            let env = mock_env(before_all.default_sender_owner.clone(), &[]);
+            handle(&mut deps, env, HandleMsg::Renounce {}).unwrap();
+            let state = config_read(&deps.storage).load().unwrap();
+            assert_eq!(state.guardian, None);
+        }
+    }
+    mod renounce {
+        use super::*;
+        // handle_renounce
+        #[test]
+        fn only_admin() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env = mock_env(before_all.default_sender_two, &[]);
+
+            let res = handle_renounce(&mut deps, env);
+            match res {
+                Err(StdError::Unauthorized { .. }) => {}
+                _ => panic!("Unexpected error"),
+            }
+        }
+        #[test]
+        fn safe_lock_on() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env = mock_env(before_all.default_sender_owner, &[]);
+
+            let mut state = config(&mut deps.storage).load().unwrap();
+            state.pause = PauseFlags::all();
+            config(&mut deps.storage).save(&state).unwrap();
+
+            let res = handle_renounce(&mut deps, env);
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, "Contract is locked");
+                }
+                _ => panic!("Unexpected error"),
+            }
+        }
+        #[test]
+        fn success() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env = mock_env(before_all.default_sender_owner.clone(), &[]);
+
+            // Transfer power to admin
+            let res = handle_renounce(&mut deps, env.clone()).unwrap();
+            assert_eq!(res.messages.len(), 0);
+            let state = config(&mut deps.storage).load().unwrap();
+            assert_ne!(
+                state.admin,
+                deps.api
+                    .canonical_address(&before_all.default_sender_owner)
+                    .unwrap()
+            );
+            assert_eq!(
+                state.admin,
+                deps.api.canonical_address(&env.contract.address).unwrap()
+            );
+        }
+    }
+
+    mod admin_actions {
+        use super::*;
+        // handle_queue_admin_action, handle_execute_admin_action, handle_cancel_admin_action
+        fn init_with_delay<S: Storage, A: Api, Q: Querier>(deps: &mut Extern<S, A, Q>) {
+            default_init(deps);
+            let mut state = config(&mut deps.storage).load().unwrap();
+            state.timelock_delay = 50;
+            config(&mut deps.storage).save(&state).unwrap();
+        }
+
+        #[test]
+        fn queued_until_delay() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            init_with_delay(&mut deps);
+            let env_admin = mock_env(before_all.default_sender_owner.clone(), &[]);
+            let msg = HandleMsg::SetInstantUnStakePenalty {
+                rate: Decimal::percent(5),
+                destination: PenaltyDestination::Burn,
+                treasury: None,
+            };
+            let res = handle(&mut deps, env_admin.clone(), msg.clone()).unwrap();
+            let execute_after = env_admin.block.height + 50;
+            assert_eq!(
+                res.log[2],
+                LogAttribute {
+                    key: "execute_after".to_string(),
+                    value: execute_after.to_string(),
+                }
+            );
+            assert_eq!(
+                query_config(&deps).unwrap().instant_unstake_penalty,
+                Decimal::zero()
+            );
+            assert_eq!(
+                query_pending_admin_actions(&deps).unwrap().actions,
+                vec![AdminAction {
+                    id: 0,
+                    msg,
+                    execute_after,
+                }]
+            );
+
+            let mut env = mock_env(before_all.default_sender.clone(), &[]);
+            env.block.height = execute_after - 1;
+            let res = handle(
+                &mut deps,
+                env.clone(),
+                HandleMsg::ExecuteAdminAction { id: 0 },
+            );
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(
+                        msg,
+                        format!(
+                            "Admin action 0 can be executed from block {}",
+                            execute_after
+                        )
+                    );
+                }
+                _ => panic!("Unexpected error"),
+            }
+
+            // Anyone can execute it once the delay passed
+            env.block.height = execute_after;
+            handle(
+                &mut deps,
+                env.clone(),
+                HandleMsg::ExecuteAdminAction { id: 0 },
+            )
+            .unwrap();
+            assert_eq!(
+                query_config(&deps).unwrap().instant_unstake_penalty,
+                Decimal::percent(5)
+            );
+            assert_eq!(query_pending_admin_actions(&deps).unwrap().actions, vec![]);
+            let res = handle(&mut deps, env, HandleMsg::ExecuteAdminAction { id: 0 });
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, "Admin action 0 not found");
+                }
+                _ => panic!("Unexpected error"),
+            }
+        }
+
+        #[test]
+        fn cancel_by_admin() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            init_with_delay(&mut deps);
+            let env_admin = mock_env(before_all.default_sender_owner.clone(), &[]);
+            handle(&mut deps, env_admin.clone(), HandleMsg::Renounce {}).unwrap();
+
+            let env = mock_env(before_all.default_sender, &[]);
+            let res = handle(&mut deps, env, HandleMsg::CancelAdminAction { id: 0 });
+            match res {
+                Err(StdError::Unauthorized { .. }) => {}
+                _ => panic!("Unexpected error"),
+            }
+            handle(&mut deps, env_admin, HandleMsg::CancelAdminAction { id: 0 }).unwrap();
+            assert_eq!(query_pending_admin_actions(&deps).unwrap().actions, vec![]);
+        }
+
+        #[test]
+        fn safe_lock_not_queued() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            init_with_delay(&mut deps);
+            let env_admin = mock_env(before_all.default_sender_owner, &[]);
+            handle(&mut deps, env_admin, HandleMsg::SafeLock {}).unwrap();
+            assert_eq!(query_config(&deps).unwrap().pause, PauseFlags::all());
+            assert_eq!(query_pending_admin_actions(&deps).unwrap().actions, vec![]);
+        }
+
+        #[test]
+        fn cancel_admin_proposal_not_queued() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env_admin = mock_env(before_all.default_sender_owner.clone(), &[]);
+            let msg = HandleMsg::ProposeNewAdmin {
+                address: before_all.default_sender.clone(),
+                expiry: None,
+            };
+            handle(&mut deps, env_admin.clone(), msg).unwrap();
+            let mut state = config(&mut deps.storage).load().unwrap();
+            state.timelock_delay = 100;
+            config(&mut deps.storage).save(&state).unwrap();
+
+            handle(&mut deps, env_admin, HandleMsg::CancelAdminProposal {}).unwrap();
+            assert_eq!(query_config(&deps).unwrap().pending_admin, None);
+            assert_eq!(query_pending_admin_actions(&deps).unwrap().actions, vec![]);
+            let env = mock_env(before_all.default_sender, &[]);
+            let res = handle(&mut deps, env, HandleMsg::AcceptAdmin {});
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, "No admin proposal");
+                }
+                _ => panic!("Unexpected error"),
+            }
+        }
+
+        #[test]
+        fn remove_guardian_not_queued() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env_admin = mock_env(before_all.default_sender_owner.clone(), &[]);
+            let msg = HandleMsg::SetGuardian {
+                guardian: Some(before_all.default_sender_two.clone()),
+            };
+            handle(&mut deps, env_admin.clone(), msg.clone()).unwrap();
+            let mut state = config(&mut deps.storage).load().unwrap();
+            state.timelock_delay = 100;
+            config(&mut deps.storage).save(&state).unwrap();
+
+            // Setting a guardian still waits for the delay
+            handle(&mut deps, env_admin.clone(), msg).unwrap();
+            assert_eq!(query_pending_admin_actions(&deps).unwrap().actions.len(), 1);
+
+            let msg = HandleMsg::SetGuardian { guardian: None };
+            handle(&mut deps, env_admin, msg).unwrap();
+            assert_eq!(query_config(&deps).unwrap().guardian, None);
+            assert_eq!(query_pending_admin_actions(&deps).unwrap().actions.len(), 1);
+            let env = mock_env(before_all.default_sender_two, &[]);
+            let res = handle(&mut deps, env, HandleMsg::SafeLock {});
+            match res {
+                Err(StdError::Unauthorized { .. }) => {}
+                _ => panic!("Unexpected error"),
+            }
+        }
+
+        #[test]
+        fn dropped_on_admin_change() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            init_with_delay(&mut deps);
+            let env_admin = mock_env(before_all.default_sender_owner.clone(), &[]);
+            let msg = HandleMsg::SetInstantUnStakePenalty {
+                rate: Decimal::percent(5),
+                destination: PenaltyDestination::Burn,
+                treasury: None,
+            };
+            handle(&mut deps, env_admin.clone(), msg).unwrap();
+            let msg = HandleMsg::ProposeNewAdmin {
+                address: before_all.default_sender.clone(),
+                expiry: None,
+            };
+            handle(&mut deps, env_admin.clone(), msg).unwrap();
+
+            let mut env = mock_env(before_all.default_sender.clone(), &[]);
+            env.block.height = env_admin.block.height + 50;
+            handle(
+                &mut deps,
+                env.clone(),
+                HandleMsg::ExecuteAdminAction { id: 1 },
+            )
+            .unwrap();
+            handle(&mut deps, env.clone(), HandleMsg::AcceptAdmin {}).unwrap();
+
+            // The action queued by the previous admin can't run under the new one
+            assert_eq!(query_pending_admin_actions(&deps).unwrap().actions, vec![]);
+            let res = handle(&mut deps, env, HandleMsg::ExecuteAdminAction { id: 0 });
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, "Admin action 0 not found");
+                }
+                _ => panic!("Unexpected error"),
+            }
+            assert_eq!(
+                query_config(&deps).unwrap().instant_unstake_penalty,
+                Decimal::zero()
+            );
+        }
+    }
+
+    mod admin_transfer {
+        use super::*;
+        // handle_propose_new_admin, handle_accept_admin, handle_cancel_admin_proposal
+        #[test]
+        fn only_admin() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env = mock_env(before_all.default_sender.clone(), &[]);
+            let msg = HandleMsg::ProposeNewAdmin {
+                address: before_all.default_sender,
+                expiry: None,
+            };
+            let res = handle(&mut deps, env.clone(), msg);
+            match res {
+                Err(StdError::Unauthorized { .. }) => {}
+                _ => panic!("Unexpected error"),
+            }
+            let res = handle(&mut deps, env, HandleMsg::CancelAdminProposal {});
+            match res {
+                Err(StdError::Unauthorized { .. }) => {}
+                _ => panic!("Unexpected error"),
+            }
+        }
+
+        #[test]
+        fn accept_only_by_proposed() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env_admin = mock_env(before_all.default_sender_owner.clone(), &[]);
+            let msg = HandleMsg::ProposeNewAdmin {
+                address: before_all.default_sender.clone(),
+                expiry: None,
+            };
+            handle(&mut deps, env_admin, msg).unwrap();
+            let env = mock_env(before_all.default_sender_two, &[]);
+            let res = handle(&mut deps, env, HandleMsg::AcceptAdmin {});
+            match res {
+                Err(StdError::Unauthorized { .. }) => {}
+                _ => panic!("Unexpected error"),
+            }
+
+            let env = mock_env(before_all.default_sender.clone(), &[]);
+            handle(&mut deps, env, HandleMsg::AcceptAdmin {}).unwrap();
+            let state = query_config(&deps).unwrap();
+            assert_eq!(
+                state.admin,
+                deps.api
+                    .canonical_address(&before_all.default_sender)
+                    .unwrap()
+            );
+            assert_eq!(state.pending_admin, None);
+        }
+
+        #[test]
+        fn proposal_expired() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env_admin = mock_env(before_all.default_sender_owner.clone(), &[]);
+            let expiry = env_admin.block.height + 10;
+            let msg = HandleMsg::ProposeNewAdmin {
+                address: before_all.default_sender.clone(),
+                expiry: Some(expiry),
+            };
+            handle(&mut deps, env_admin, msg).unwrap();
+            let state = query_config(&deps).unwrap();
+            assert_eq!(
+                state.pending_admin,
+                Some(PendingAdmin {
+                    address: deps
+                        .api
+                        .canonical_address(&before_all.default_sender)
+                        .unwrap(),
+                    expiry: Some(expiry),
+                })
+            );
+
+            let mut env = mock_env(before_all.default_sender, &[]);
+            env.block.height = expiry;
+            let res = handle(&mut deps, env, HandleMsg::AcceptAdmin {});
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, "Admin proposal expired");
+                }
+                _ => panic!("Unexpected error"),
+            }
+        }
+
+        #[test]
+        fn cancel_proposal() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env_admin = mock_env(before_all.default_sender_owner.clone(), &[]);
+            let msg = HandleMsg::ProposeNewAdmin {
+                address: before_all.default_sender.clone(),
+                expiry: None,
+            };
+            handle(&mut deps, env_admin.clone(), msg).unwrap();
+            handle(&mut deps, env_admin, HandleMsg::CancelAdminProposal {}).unwrap();
+
+            let env = mock_env(before_all.default_sender, &[]);
+            let res = handle(&mut deps, env, HandleMsg::AcceptAdmin {});
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, "No admin proposal");
+                }
+                _ => panic!("Unexpected error"),
+            }
+        }
+    }
+
+    mod update_config {
+        use super::*;
+        // handle_update_config
+        fn no_update() -> HandleMsg {
+            HandleMsg::UpdateConfig {
+                unbonded_period: None,
+                reward_denoms: None,
+                reward_tokens: None,
+                address_cw20_loterra_smart_contract: None,
+                max_unbonding_entries: None,
+                carry_reward_remainder: None,
+                lock_tiers: None,
+                min_bonding_age: None,
+                timelock_delay: None,
+                emergency_withdraw_delay: None,
+                max_pause_duration: None,
+                governance: None,
+            }
+        }
+
+        #[test]
+        fn only_admin() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env = mock_env(before_all.default_sender, &[]);
+            let res = handle(&mut deps, env, no_update());
+            match res {
+                Err(StdError::Unauthorized { .. }) => {}
+                _ => panic!("Unexpected error"),
+            }
+        }
+
+        #[test]
+        fn unbonded_period_not_retroactive() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env = mock_env(before_all.default_sender.clone(), &[]);
+            let msg = HandleMsg::Stake {
+                amount: Uint128(1_000),
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
+            let msg = HandleMsg::UnStake {
+                amount: Uint128(100),
+            };
+            handle(&mut deps, env.clone(), msg.clone()).unwrap();
+
+            let env_admin = mock_env(before_all.default_sender_owner, &[]);
+            let mut update = no_update();
+            if let HandleMsg::UpdateConfig {
+                unbonded_period, ..
+            } = &mut update
+            {
+                *unbonded_period = Some(500);
+            }
+            let res = handle(&mut deps, env_admin, update).unwrap();
+            assert_eq!(
+                res.log[1..],
+                [
+                    LogAttribute {
+                        key: "old_unbonded_period".to_string(),
+                        value: "100".to_string(),
+                    },
+                    LogAttribute {
+                        key: "new_unbonded_period".to_string(),
+                        value: "500".to_string(),
+                    },
+                ]
+            );
+
+            let mut env = env;
+            env.block.height += 1;
+            handle(&mut deps, env.clone(), msg).unwrap();
+            let holder = query_holder(&deps, before_all.default_sender).unwrap();
+            assert_eq!(
+                holder.unbonding,
+                vec![
+                    UnbondingInfo {
+                        amount: Uint128(100),
+                        release_height: env.block.height - 1 + 100,
+                    },
+                    UnbondingInfo {
+                        amount: Uint128(100),
+                        release_height: env.block.height + 500,
+                    },
+                ]
+            );
+        }
+
+        #[test]
+        fn denom_removal() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env = mock_env(before_all.default_sender.clone(), &[]);
+            let msg = HandleMsg::Stake {
+                amount: Uint128(1_000),
+            };
+            handle(&mut deps, env, msg).unwrap();
+
+            // Adding a denom keeps the uusd index
+            let env_admin = mock_env(before_all.default_sender_owner.clone(), &[]);
+            let mut update = no_update();
+            if let HandleMsg::UpdateConfig { reward_denoms, .. } = &mut update {
+                *reward_denoms = Some(vec!["uusd".to_string(), "ukrw".to_string()]);
+            }
+            handle(&mut deps, env_admin.clone(), update).unwrap();
+            let env_payer = mock_env(
+                before_all.default_contract_address.clone(),
+                &coins(1_000, "uusd"),
+            );
+            handle(&mut deps, env_payer, HandleMsg::PayoutReward {}).unwrap();
+
+            let mut update = no_update();
+            if let HandleMsg::UpdateConfig { reward_denoms, .. } = &mut update {
+                *reward_denoms = Some(vec!["ukrw".to_string()]);
+            }
+            let res = handle(&mut deps, env_admin.clone(), update);
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(
+                        msg,
+                        "Can't remove reward denom uusd, denoms can only be added"
+                    );
+                }
+                _ => panic!("Unexpected error"),
+            }
+            // Even a denom never paid out stays whitelisted
+            let mut update = no_update();
+            if let HandleMsg::UpdateConfig { reward_denoms, .. } = &mut update {
+                *reward_denoms = Some(vec!["uusd".to_string()]);
+            }
+            let res = handle(&mut deps, env_admin, update);
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(
+                        msg,
+                        "Can't remove reward denom ukrw, denoms can only be added"
+                    );
+                }
+                _ => panic!("Unexpected error"),
+            }
+            let state = query_config(&deps).unwrap();
+            assert_eq!(state.reward_indexes.len(), 2);
+            assert!(!state.reward_indexes[0].index.is_zero());
+        }
+
+        #[test]
+        fn token_addition() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env = mock_env(before_all.default_sender.clone(), &[]);
+            let msg = HandleMsg::Stake {
+                amount: Uint128(1_000),
+            };
+            handle(&mut deps, env, msg).unwrap();
+
+            let env_admin = mock_env(before_all.default_sender_owner.clone(), &[]);
+            let mut update = no_update();
+            if let HandleMsg::UpdateConfig { reward_tokens, .. } = &mut update {
+                *reward_tokens = Some(vec![before_all.default_contract_address_two.clone()]);
+            }
+            let res = handle(&mut deps, env_admin.clone(), update).unwrap();
+            assert_eq!(
+                res.log[1..],
+                [
+                    LogAttribute {
+                        key: "old_reward_tokens".to_string(),
+                        value: "".to_string(),
+                    },
+                    LogAttribute {
+                        key: "new_reward_tokens".to_string(),
+                        value: before_all.default_contract_address_two.to_string(),
+                    },
+                ]
+            );
+
+            // The whitelisted token can be paid out
+            let env_token = mock_env(before_all.default_contract_address_two.clone(), &[]);
+            let msg = HandleMsg::Receive(Cw20ReceiveMsg {
+                sender: before_all.default_sender_owner.clone(),
+                amount: Uint128(1_000),
+                msg: Some(to_binary(&ReceiveMsg::PayoutReward {}).unwrap()),
+            });
+            handle(&mut deps, env_token, msg).unwrap();
+            let holder = query_holder(&deps, before_all.default_sender.clone()).unwrap();
+            assert_eq!(
+                holder.available_tokens,
+                vec![TokenBalanceResponse {
+                    token: before_all.default_contract_address_two.clone(),
+                    amount: Uint128(1_000),
+                }]
+            );
+
+            let mut update = no_update();
+            if let HandleMsg::UpdateConfig { reward_tokens, .. } = &mut update {
+                *reward_tokens = Some(vec![]);
+            }
+            let res = handle(&mut deps, env_admin, update);
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(
+                        msg,
+                        format!(
+                            "Can't remove reward token {}, tokens can only be added",
+                            before_all.default_contract_address_two
+                        )
+                    );
+                }
+                _ => panic!("Unexpected error"),
+            }
+            assert_totals_consistent(&deps);
+        }
+
+        #[test]
+        fn cw20_change_while_staked() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env = mock_env(before_all.default_sender.clone(), &[]);
+            let msg = HandleMsg::Stake {
+                amount: Uint128(1_000),
+            };
+            handle(&mut deps, env, msg).unwrap();
+            let env_admin = mock_env(before_all.default_sender_owner, &[]);
+            let mut update = no_update();
+            if let HandleMsg::UpdateConfig {
+                address_cw20_loterra_smart_contract,
+                ..
+            } = &mut update
+            {
+                *address_cw20_loterra_smart_contract =
+                    Some(before_all.default_contract_address_two);
+            }
+            let res = handle(&mut deps, env_admin, update);
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(
+                        msg,
+                        "Can't change the LoTerra cw-20 while tokens are staked"
+                    );
+                }
+                _ => panic!("Unexpected error"),
+            }
+        }
+
+        #[test]
+        fn zero_emergency_withdraw_delay() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env_admin = mock_env(before_all.default_sender_owner, &[]);
+            let mut update = no_update();
+            if let HandleMsg::UpdateConfig {
+                emergency_withdraw_delay,
+                ..
+            } = &mut update
+            {
+                *emergency_withdraw_delay = Some(0);
+            }
+            let res = handle(&mut deps, env_admin, update);
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, "Emergency withdraw delay must be at least 1");
+                }
+                _ => panic!("Unexpected error"),
+            }
+        }
+    }
+
+    mod recompute_totals {
+        use super::*;
+        // handle_recompute_totals
+        #[test]
+        fn only_admin() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env = mock_env(before_all.default_sender_two, &[]);
+
+            let res = handle_recompute_totals(&mut deps, env);
+            match res {
+                Err(StdError::Unauthorized { .. }) => {}
+                _ => panic!("Unexpected error"),
+            }
+        }
+        #[test]
+        fn success() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env = mock_env(before_all.default_sender.clone(), &[]);
+            let msg = HandleMsg::Stake {
+                amount: Uint128(2_000),
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
+            let msg = HandleMsg::UnStake {
+                amount: Uint128(500),
+            };
+            handle(&mut deps, env, msg).unwrap();
+            let env = mock_env(before_all.default_sender_two.clone(), &[]);
+            let msg = HandleMsg::Stake {
+                amount: Uint128(3_000),
+            };
+            handle(&mut deps, env, msg).unwrap();
+
+            // Simulate counters out of sync with the holders
+            let mut state = config(&mut deps.storage).load().unwrap();
+            state.total_bonded = Uint128::zero();
+            state.total_unbonding = Uint128(42);
+            config(&mut deps.storage).save(&state).unwrap();
+
+            let env = mock_env(before_all.default_sender_owner, &[]);
+            let res = handle(&mut deps, env, HandleMsg::RecomputeTotals {}).unwrap();
+            assert_eq!(res.messages.len(), 0);
+            let all_bonded = query_all_bonded(&deps).unwrap();
+            assert_eq!(all_bonded.total_bonded, Uint128(4_500));
+            assert_eq!(all_bonded.total_unbonding, Uint128(500));
+            assert_totals_consistent(&deps);
+        }
+    }
+
+    mod stake {
+        use super::*;
+        // handle_stake
+        #[test]
+        fn do_not_send_funds() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env = mock_env(
+                before_all.default_sender_owner.clone(),
+                &[Coin {
+                    denom: "x".to_string(),
+                    amount: Uint128(2_000),
+                }],
+            );
+            let msg = HandleMsg::Stake {
+                amount: Uint128(2_000),
+            };
+            let res = handle(&mut deps, env.clone(), msg.clone());
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, "Do not send funds with stake");
+                }
+                _ => panic!("Unexpected error"),
+            }
+        }
+        #[test]
+        fn amount_required(){
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env = mock_env(
+                before_all.default_sender_owner.clone(),
+                &[],
+            );
+            let msg = HandleMsg::Stake {
+                amount: Uint128(0),
+            };
+            let res = handle(&mut deps, env.clone(), msg.clone());
+            match res {
+                Err(GenericErr {
+                        msg,
+                        backtrace: None,
+                    }) => {
+                    assert_eq!(msg, "Amount required");
+                }
+                _ => panic!("Unexpected error"),
+            }
+        }
+        #[test]
+        fn success() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env = mock_env(before_all.default_sender_owner.clone(), &[]);
+            let msg = HandleMsg::Stake {
+                amount: Uint128(2_000),
+            };
+            let res = handle(&mut deps, env.clone(), msg.clone()).unwrap();
+            assert_eq!(res.messages.len(), 1);
+            assert_eq!(
+                res.messages[0],
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: before_all.default_contract_address,
+                    msg: Binary::from(r#"{"transfer_from":{"owner":"terra1q88h7ewu6h3am4mxxeqhu3srt7zw4z5s20qu3k","recipient":"cosmos2contract","amount":"2000"}}"#.as_bytes()),
+                    send: vec![]
+                })
+            );
+            let store = staking_storage(&mut deps.storage)
+                .load(
+                    &deps
+                        .api
+                        .canonical_address(&before_all.default_sender_owner)
+                        .unwrap()
+                        .as_slice(),
+                )
+                .unwrap();
+            assert_eq!(store.bonded, Uint128(2_000));
+            assert_eq!(store.un_bonded, Uint128::zero());
+            assert!(store.available.is_empty());
+            assert!(store.unbonding.is_empty());
+
+            // Stake more
+            let res = handle(&mut deps, env.clone(), msg.clone()).unwrap();
+            let store = staking_storage(&mut deps.storage)
+                .load(
+                    &deps
+                        .api
+                        .canonical_address(&before_all.default_sender_owner)
+                        .unwrap()
+                        .as_slice(),
+                )
+                .unwrap();
+            assert_eq!(store.bonded, Uint128(4_000));
+            assert_eq!(store.un_bonded, Uint128::zero());
+            assert!(store.available.is_empty());
+            assert!(store.unbonding.is_empty());
+
+            let all_bonded = query_all_bonded(&deps).unwrap();
+            assert_eq!(all_bonded.total_bonded, Uint128(4_000));
+            assert_eq!(all_bonded.total_unbonding, Uint128::zero());
+            assert_totals_consistent(&deps);
+        }
+    }
+    mod get_holders {
+        use super::*;
+        // query_holders
+
+        fn addresses(res: &GetHoldersResponse) -> Vec<HumanAddr> {
+            res.holders.iter().map(|h| h.address.clone()).collect()
+        }
+
+        #[test]
+        fn pagination() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let stakers = [
+                (before_all.default_sender.clone(), 1_000),
+                (before_all.default_sender_two.clone(), 2_000),
+                (before_all.default_sender_owner.clone(), 3_000),
+            ];
+            for (staker, amount) in stakers.iter() {
+                let env = mock_env(staker.clone(), &[]);
+                let msg = HandleMsg::Stake {
+                    amount: Uint128(*amount),
+                };
+                handle(&mut deps, env, msg).unwrap();
+            }
+            let env = mock_env(before_all.default_sender.clone(), &[]);
+            let msg = HandleMsg::UnStake {
+                amount: Uint128(400),
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
+
+            let all = query_holders(&deps, None, None, None).unwrap();
+            assert_eq!(all.holders.len(), 3);
+            let holder = all
+                .holders
+                .iter()
+                .find(|h| h.address == before_all.default_sender)
+                .unwrap();
+            assert_eq!(
+                holder,
+                &GetBondedResponse {
+                    address: before_all.default_sender.clone(),
+                    bonded: Uint128(600),
+                    un_bonded: Uint128(400),
+                    available: vec![],
+                    period: env.block.height + 100,
+                }
+            );
+
+            let first = query_holders(&deps, None, Some(2), Some(OrderBy::Asc)).unwrap();
+            assert_eq!(first.holders.len(), 2);
+            let next = query_holders(
+                &deps,
+                Some(first.holders[1].address.clone()),
+                Some(2),
+                Some(OrderBy::Asc),
+            )
+            .unwrap();
+            assert_eq!(next.holders.len(), 1);
+            let mut pages = addresses(&first);
+            pages.extend(addresses(&next));
+            assert_eq!(pages, addresses(&all));
+
+            let desc = query_holders(&deps, None, None, Some(OrderBy::Desc)).unwrap();
+            let mut reversed = addresses(&all);
+            reversed.reverse();
+            assert_eq!(addresses(&desc), reversed);
+            let desc_next = query_holders(
+                &deps,
+                Some(reversed[0].clone()),
+                Some(1),
+                Some(OrderBy::Desc),
+            )
+            .unwrap();
+            assert_eq!(addresses(&desc_next), vec![reversed[1].clone()]);
+
+            // Bigger limits are capped
+            let res = query(
+                &deps,
+                QueryMsg::GetHolders {
+                    start_after: None,
+                    limit: Some(MAX_HOLDERS_LIMIT + 1),
+                    order: None,
+                },
+            )
+            .unwrap();
+            let res: GetHoldersResponse = from_binary(&res).unwrap();
+            assert_eq!(res, all);
+        }
+
+        #[test]
+        fn max_page_size() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            for i in 0..MAX_HOLDERS_LIMIT + 5 {
+                let staker =
+                    HumanAddr::from(format!("terra1q88h7ewu6h3am4mxxeqhu3srt7zw4z5s2{:04}", i));
+                let env = mock_env(staker, &[]);
+                let msg = HandleMsg::Stake {
+                    amount: Uint128(1_000),
+                };
+                handle(&mut deps, env, msg).unwrap();
+            }
+            let res = query_holders(&deps, None, Some(u32::MAX), None).unwrap();
+            assert_eq!(res.holders.len(), MAX_HOLDERS_LIMIT as usize);
+            let res = query_holders(&deps, None, None, None).unwrap();
+            assert_eq!(res.holders.len(), DEFAULT_HOLDERS_LIMIT as usize);
+        }
+    }
+    mod top_stakers {
+        use super::*;
+        // query_top_stakers
+
+        fn ranking<S: Storage, A: Api, Q: Querier>(
+            deps: &Extern<S, A, Q>,
+        ) -> Vec<(HumanAddr, Uint128)> {
+            query_top_stakers(deps, None, None)
+                .unwrap()
+                .stakers
+                .into_iter()
+                .map(|staker| (staker.address, staker.bonded))
+                .collect()
+        }
+
+        #[test]
+        fn kept_in_sync() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let stakers = [
+                (before_all.default_sender.clone(), 1_000),
+                (before_all.default_sender_two.clone(), 3_000),
+                (before_all.default_sender_owner.clone(), 2_000),
+            ];
+            for (staker, amount) in stakers.iter() {
+                let env = mock_env(staker.clone(), &[]);
+                let msg = HandleMsg::Stake {
+                    amount: Uint128(*amount),
+                };
+                handle(&mut deps, env, msg).unwrap();
+            }
+
+            let res = query_top_stakers(&deps, None, None).unwrap();
+            assert_eq!(
+                res.stakers[0],
+                TopStakerResponse {
+                    address: before_all.default_sender_two.clone(),
+                    bonded: Uint128(3_000),
+                    share: Decimal::percent(50),
+                }
+            );
+            assert_eq!(
+                ranking(&deps),
+                vec![
+                    (before_all.default_sender_two.clone(), Uint128(3_000)),
+                    (before_all.default_sender_owner.clone(), Uint128(2_000)),
+                    (before_all.default_sender.clone(), Uint128(1_000)),
+                ]
+            );
+
+            let env = mock_env(before_all.default_sender_two.clone(), &[]);
+            let msg = HandleMsg::UnStake {
+                amount: Uint128(2_500),
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
+            assert_eq!(
+                ranking(&deps),
+                vec![
+                    (before_all.default_sender_owner.clone(), Uint128(2_000)),
+                    (before_all.default_sender.clone(), Uint128(1_000)),
+                    (before_all.default_sender_two.clone(), Uint128(500)),
+                ]
+            );
+
+            let msg = HandleMsg::ReBond {
+                amount: Uint128(2_000),
+            };
+            handle(&mut deps, env, msg).unwrap();
+            assert_eq!(
+                ranking(&deps),
+                vec![
+                    (before_all.default_sender_two.clone(), Uint128(2_500)),
+                    (before_all.default_sender_owner.clone(), Uint128(2_000)),
+                    (before_all.default_sender.clone(), Uint128(1_000)),
+                ]
+            );
+
+            // Holders leaving entirely drop out of the index
+            let env = mock_env(before_all.default_sender.clone(), &[]);
+            let msg = HandleMsg::UnStake {
+                amount: Uint128(1_000),
+            };
+            handle(&mut deps, env, msg).unwrap();
+            assert_eq!(ranking(&deps).len(), 2);
+        }
+
+        #[test]
+        fn pagination() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let stakers = [
+                (before_all.default_sender.clone(), 1_000),
+                (before_all.default_sender_two.clone(), 1_000),
+                (before_all.default_sender_owner.clone(), 2_000),
+            ];
+            for (staker, amount) in stakers.iter() {
+                let env = mock_env(staker.clone(), &[]);
+                let msg = HandleMsg::Stake {
+                    amount: Uint128(*amount),
+                };
+                handle(&mut deps, env, msg).unwrap();
+            }
+
+            let all = ranking(&deps);
+            let first = query_top_stakers(&deps, Some(2), None).unwrap();
+            assert_eq!(first.stakers.len(), 2);
+            assert_eq!(first.stakers[0].address, before_all.default_sender_owner);
+            let res = query(
+                &deps,
+                QueryMsg::TopStakers {
+                    limit: Some(2),
+                    start_after: Some(first.stakers[1].address.clone()),
+                },
+            )
+            .unwrap();
+            let next: TopStakersResponse = from_binary(&res).unwrap();
+            assert_eq!(next.stakers.len(), 1);
+            assert_eq!(next.stakers[0].address, all[2].0);
+            assert_eq!(next.stakers[0].share, Decimal::percent(25));
+        }
+    }
+    mod receive {
+        use super::*;
+        // handle_receive
+        #[test]
+        fn only_loterra_cw20() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env = mock_env(before_all.default_contract_address_two.clone(), &[]);
+            let msg = HandleMsg::Receive(Cw20ReceiveMsg {
+                sender: before_all.default_sender.clone(),
+                amount: Uint128(2_000),
+                msg: Some(to_binary(&ReceiveMsg::Stake {}).unwrap()),
+            });
+            let res = handle(&mut deps, env, msg);
+            match res {
+                Err(StdError::Unauthorized { .. }) => {}
+                _ => panic!("Unexpected error"),
+            }
+        }
+        #[test]
+        fn receive_message_required() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env = mock_env(before_all.default_contract_address.clone(), &[]);
+            let msg = HandleMsg::Receive(Cw20ReceiveMsg {
+                sender: before_all.default_sender.clone(),
+                amount: Uint128(2_000),
+                msg: None,
+            });
+            let res = handle(&mut deps, env, msg);
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, "Receive message required");
+                }
+                _ => panic!("Unexpected error"),
+            }
+        }
+        #[test]
+        fn success() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env = mock_env(before_all.default_contract_address.clone(), &[]);
+            let msg = HandleMsg::Receive(Cw20ReceiveMsg {
+                sender: before_all.default_sender.clone(),
+                amount: Uint128(2_000),
+                msg: Some(Binary::from(r#"{"stake":{}}"#.as_bytes())),
+            });
+            let res = handle(&mut deps, env, msg).unwrap();
+            assert_eq!(res.messages.len(), 0);
+
+            // Allowance path shares the same accounting
+            let env = mock_env(before_all.default_sender.clone(), &[]);
+            let msg = HandleMsg::Stake {
+                amount: Uint128(1_000),
+            };
+            handle(&mut deps, env, msg).unwrap();
+
+            let holder = query_holder(&deps, before_all.default_sender.clone()).unwrap();
+            assert_eq!(holder.bonded, Uint128(3_000));
+            let all_bonded = query_all_bonded(&deps).unwrap();
+            assert_eq!(all_bonded.total_bonded, Uint128(3_000));
+            assert_totals_consistent(&deps);
+        }
+    }
+    mod receive_payout_reward {
+        use super::*;
+        // handle_receive_payout_reward
+        fn token_init<S: Storage, A: Api, Q: Querier>(deps: &mut Extern<S, A, Q>) {
+            let before_all = before_all();
+            let init_msg = InitMsg {
+                address_cw20_loterra_smart_contract: before_all.default_contract_address,
+                unbonded_period: 100,
+                reward_denoms: vec!["uusd".to_string()],
+                reward_tokens: vec![before_all.default_contract_address_two],
+                max_unbonding_entries: 3,
+                carry_reward_remainder: false,
+                lock_tiers: vec![],
+                min_bonding_age: 0,
+                timelock_delay: 0,
+                emergency_withdraw_delay: 1_000,
+                max_pause_duration: 50_000,
+                governance: GovernanceConfig {
+                    proposal_min_stake: Uint128(1_000),
+                    quorum: Decimal::percent(30),
+                    vote_threshold: Decimal::percent(50),
+                    voting_period: 100,
+                },
+            };
+            init(
+                deps,
+                mock_env(before_all.default_sender_owner, &[]),
+                init_msg,
+            )
+            .unwrap();
+        }
+        #[test]
+        fn only_whitelisted_token() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            token_init(&mut deps);
+            // The staking token isn't whitelisted as reward
+            let env = mock_env(before_all.default_contract_address.clone(), &[]);
+            let msg = HandleMsg::Receive(Cw20ReceiveMsg {
+                sender: before_all.default_sender.clone(),
+                amount: Uint128(2_000),
+                msg: Some(to_binary(&ReceiveMsg::PayoutReward {}).unwrap()),
+            });
+            let res = handle(&mut deps, env, msg);
+            match res {
+                Err(StdError::Unauthorized { .. }) => {}
+                _ => panic!("Unexpected error"),
+            }
+        }
+        #[test]
+        fn no_amount_staked() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            token_init(&mut deps);
+            let env = mock_env(before_all.default_contract_address_two.clone(), &[]);
+            let msg = HandleMsg::Receive(Cw20ReceiveMsg {
+                sender: before_all.default_sender_owner.clone(),
+                amount: Uint128(2_000),
+                msg: Some(to_binary(&ReceiveMsg::PayoutReward {}).unwrap()),
+            });
+            let res = handle(&mut deps, env, msg).unwrap();
+            assert_eq!(
+                res.messages[0],
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: before_all.default_contract_address_two.clone(),
+                    msg: Binary::from(
+                        r#"{"transfer":{"recipient":"terra1q88h7ewu6h3am4mxxeqhu3srt7zw4z5s20qu3k","amount":"2000"}}"#
+                            .as_bytes()
+                    ),
+                    send: vec![]
+                })
+            );
+        }
+        #[test]
+        fn success() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            token_init(&mut deps);
+            let env = mock_env(before_all.default_sender.clone(), &[]);
+            let msg = HandleMsg::Stake {
+                amount: Uint128(1_000),
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
+            let env_two = mock_env(before_all.default_sender_two.clone(), &[]);
+            let msg = HandleMsg::Stake {
+                amount: Uint128(3_000),
+            };
+            handle(&mut deps, env_two, msg).unwrap();
+
+            let env_token = mock_env(before_all.default_contract_address_two.clone(), &[]);
+            let msg = HandleMsg::Receive(Cw20ReceiveMsg {
+                sender: before_all.default_sender_owner.clone(),
+                amount: Uint128(8_000),
+                msg: Some(to_binary(&ReceiveMsg::PayoutReward {}).unwrap()),
+            });
+            let res = handle(&mut deps, env_token, msg).unwrap();
+            assert_eq!(res.messages.len(), 0);
+
+            let holder = query_holder(&deps, before_all.default_sender_two.clone()).unwrap();
+            assert!(holder.available.is_empty());
+            assert_eq!(
+                holder.available_tokens,
+                vec![TokenBalanceResponse {
+                    token: before_all.default_contract_address_two.clone(),
+                    amount: Uint128(6_000)
+                }]
+            );
+
+            let res = handle(&mut deps, env, HandleMsg::ClaimReward {}).unwrap();
+            assert_eq!(res.messages.len(), 1);
+            assert_eq!(
+                res.messages[0],
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: before_all.default_contract_address_two.clone(),
+                    msg: Binary::from(
+                        r#"{"transfer":{"recipient":"terra1q88h7ewu6h3am4mxxeqhu3srt7zw4z5s20q007","amount":"2000"}}"#
+                            .as_bytes()
+                    ),
+                    send: vec![]
+                })
+            );
+            let holder = query_holder(&deps, before_all.default_sender.clone()).unwrap();
+            assert!(holder.available_tokens.is_empty());
+        }
+    }
+    mod unstake {
+        use super::*;
+        // handle_unstake
+        #[test]
+        fn do_not_send_funds() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env = mock_env(
+                before_all.default_sender.clone(),
+                &[Coin {
+                    denom: "x".to_string(),
+                    amount: Uint128(2_000),
+                }],
+            );
+            let msg = HandleMsg::UnStake {
+                amount: Uint128(2_000),
+            };
+            let res = handle(&mut deps, env.clone(), msg.clone());
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, "Do not send funds with un_stake");
+                }
+                _ => panic!("Unexpected error"),
+            }
+        }
+        #[test]
+        fn no_stake_registered_from_this_address() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env = mock_env(before_all.default_sender.clone(), &[]);
+            let msg = HandleMsg::UnStake {
+                amount: Uint128(2_000),
+            };
+            let res = handle(&mut deps, env.clone(), msg.clone());
+            println!("{:?}", res);
+            match res {
+                Err(StdError::Unauthorized { .. }) => {}
+                _ => panic!("Unexpected error"),
+            }
+        }
+        #[test]
+        fn unstake_more_than_staked() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env = mock_env(before_all.default_sender.clone(), &[]);
+            // Stake some funds
+            let msg = HandleMsg::Stake {
+                amount: Uint128(2_000),
+            };
+            let res = handle(&mut deps, env.clone(), msg.clone()).unwrap();
+            // UnStake some funds
+            let msg = HandleMsg::UnStake {
+                amount: Uint128(3_000),
+            };
+            let res = handle(&mut deps, env.clone(), msg.clone());
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, "You can\'t unStake more than you have (2000)");
+                }
+                _ => panic!("Unexpected error"),
+            }
+        }
+        #[test]
+        fn amount_required(){
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env = mock_env(
+                before_all.default_sender.clone(),
+                &[],
+            );
+            let msg = HandleMsg::UnStake {
+                amount: Uint128(0),
+            };
+            let res = handle(&mut deps, env.clone(), msg.clone());
+            match res {
+                Err(GenericErr {
+                        msg,
+                        backtrace: None,
+                    }) => {
+                    assert_eq!(msg, "Amount required");
+                }
+                _ => panic!("Unexpected error"),
+            }
+        }
+        #[test]
+        fn success() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env = mock_env(before_all.default_sender.clone(), &[]);
+            // Stake some funds
+            let msg = HandleMsg::Stake {
+                amount: Uint128(2_000),
+            };
+            let res = handle(&mut deps, env.clone(), msg.clone()).unwrap();
+            // UnStake some funds
+            let msg = HandleMsg::UnStake {
+                amount: Uint128(1_500),
+            };
+            let res = handle(&mut deps, env.clone(), msg.clone()).unwrap();
+            assert_eq!(res.messages.len(), 0);
+            let state = config(&mut deps.storage).load().unwrap();
+            let store = staking_storage(&mut deps.storage)
+                .load(
+                    &deps
+                        .api
+                        .canonical_address(&before_all.default_sender)
+                        .unwrap()
+                        .as_slice(),
+                )
+                .unwrap();
+            assert_eq!(store.bonded, Uint128(500));
+            assert_eq!(store.un_bonded, Uint128(1_500));
+            assert!(store.available.is_empty());
+            assert_eq!(
+                store.unbonding,
+                vec![UnbondingInfo {
+                    amount: Uint128(1_500),
+                    release_height: env.block.height + state.unbonded_period
+                }]
+            );
+
+            let all_bonded = query_all_bonded(&deps).unwrap();
+            assert_eq!(all_bonded.total_bonded, Uint128(500));
+            assert_eq!(all_bonded.total_unbonding, Uint128(1_500));
+            assert_totals_consistent(&deps);
+        }
+        #[test]
+        fn too_many_unbonding_entries() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let mut env = mock_env(before_all.default_sender.clone(), &[]);
+            let msg = HandleMsg::Stake {
+                amount: Uint128(2_000),
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
+            let msg = HandleMsg::UnStake {
+                amount: Uint128(100),
+            };
+            for _ in 0..3 {
+                handle(&mut deps, env.clone(), msg.clone()).unwrap();
+                // Same height unstake is merged in the existing entry
+                handle(&mut deps, env.clone(), msg.clone()).unwrap();
+                env.block.height += 1;
+            }
+            let res = handle(&mut deps, env.clone(), msg.clone());
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(
+                        msg,
+                        "Too many unBonding entries (max 3), claim released tokens first"
+                    );
+                }
+                _ => panic!("Unexpected error"),
+            }
+            let holder = query_holder(&deps, before_all.default_sender.clone()).unwrap();
+            assert_eq!(holder.unbonding.len(), 3);
+            assert_eq!(holder.un_bonded, Uint128(600));
+            assert_eq!(holder.period, 12_445);
+        }
+    }
+    mod lock {
+        use super::*;
+        // handle_lock
+        #[test]
+        fn invalid_lock() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env = mock_env(before_all.default_sender.clone(), &[]);
+            let msg = HandleMsg::Stake {
+                amount: Uint128(1_000),
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
+
+            let msg = HandleMsg::Lock {
+                amount: Uint128(1_000),
+                tier: 2,
+            };
+            let res = handle(&mut deps, env.clone(), msg);
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, "Lock tier 2 not found");
+                }
+                _ => panic!("Unexpected error"),
+            }
+            let msg = HandleMsg::Lock {
+                amount: Uint128(1_001),
+                tier: 0,
+            };
+            let res = handle(&mut deps, env.clone(), msg);
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, "You can't lock more than your unlocked bonded (1000)");
+                }
+                _ => panic!("Unexpected error"),
+            }
+
+            let msg = HandleMsg::Lock {
+                amount: Uint128(500),
+                tier: 1,
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
+            let msg = HandleMsg::Lock {
+                amount: Uint128(500),
+                tier: 0,
+            };
+            let res = handle(&mut deps, env, msg);
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, "Lock tier can't be lower than the current one (1)");
+                }
+                _ => panic!("Unexpected error"),
+            }
+        }
+
+        #[test]
+        fn success_weighted_rewards() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let mut env = mock_env(before_all.default_sender.clone(), &[]);
+            let msg = HandleMsg::Stake {
+                amount: Uint128(1_000),
+            };
+            handle(&mut deps, env.clone(), msg.clone()).unwrap();
+            let env_two = mock_env(before_all.default_sender_two.clone(), &[]);
+            handle(&mut deps, env_two, msg).unwrap();
+
+            let msg = HandleMsg::Lock {
+                amount: Uint128(1_000),
+                tier: 1,
+            };
+            let res = handle(&mut deps, env.clone(), msg).unwrap();
+            let expiry = env.block.height + 5_000;
+            assert_eq!(
+                res.log[3],
+                LogAttribute {
+                    key: "expiry".to_string(),
+                    value: expiry.to_string(),
+                }
+            );
+            let holder = query_holder(&deps, before_all.default_sender.clone()).unwrap();
+            assert_eq!(
+                holder.lock,
+                Some(LockInfo {
+                    tier: 1,
+                    amount: Uint128(1_000),
+                    expiry,
+                    weight: Decimal::percent(200),
+                })
+            );
+            assert_eq!(holder.weighted, Uint128(2_000));
+            assert_totals_consistent(&deps);
+
+            // Locked stake earns twice the share of unlocked stake
+            let env_payer = mock_env(
+                before_all.default_contract_address.clone(),
+                &coins(3_000, "uusd"),
+            );
+            handle(&mut deps, env_payer, HandleMsg::PayoutReward {}).unwrap();
+            let holder = query_holder(&deps, before_all.default_sender.clone()).unwrap();
+            assert_eq!(holder.available, coins(2_000, "uusd"));
+            let holder = query_holder(&deps, before_all.default_sender_two.clone()).unwrap();
+            assert_eq!(holder.available, coins(1_000, "uusd"));
+
+            let msg = HandleMsg::UnStake { amount: Uint128(1) };
+            let res = handle(&mut deps, env.clone(), msg.clone());
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(
+                        msg,
+                        format!("Locked tokens can't be unStaked before block {}", expiry)
+                    );
+                }
+                _ => panic!("Unexpected error"),
+            }
+
+            // Anyone can bring an expired lock back to the base weight
+            let mut env_other = mock_env(before_all.default_sender_owner.clone(), &[]);
+            let msg_expire = HandleMsg::ExpireLock {
+                address: before_all.default_sender.clone(),
+            };
+            let res = handle(&mut deps, env_other.clone(), msg_expire.clone());
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, "No expired lock");
+                }
+                _ => panic!("Unexpected error"),
+            }
+            env_other.block.height = expiry;
+            handle(&mut deps, env_other, msg_expire).unwrap();
+            let holder = query_holder(&deps, before_all.default_sender.clone()).unwrap();
+            assert_eq!(holder.lock, None);
+            assert_eq!(holder.weighted, Uint128(1_000));
+            assert_totals_consistent(&deps);
+
+            env.block.height = expiry;
+            handle(&mut deps, env, msg).unwrap();
+            assert_totals_consistent(&deps);
+        }
+
+        #[test]
+        fn lock_expires_without_call() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env = mock_env(before_all.default_sender.clone(), &[]);
+            let msg = HandleMsg::Stake {
+                amount: Uint128(1_000),
+            };
+            handle(&mut deps, env.clone(), msg.clone()).unwrap();
+            let env_two = mock_env(before_all.default_sender_two.clone(), &[]);
+            handle(&mut deps, env_two, msg).unwrap();
+            let msg = HandleMsg::Lock {
+                amount: Uint128(1_000),
+                tier: 1,
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
+            let expiry = env.block.height + 5_000;
+
+            // Nobody expires the lock, a later payout is shared at the base weight
+            let mut env_payer = mock_env(
+                before_all.default_contract_address.clone(),
+                &coins(3_000, "uusd"),
+            );
+            env_payer.block.height = expiry + 10_000;
+            handle(&mut deps, env_payer, HandleMsg::PayoutReward {}).unwrap();
+            let holder = query_holder(&deps, before_all.default_sender.clone()).unwrap();
+            assert_eq!(holder.lock, None);
+            assert_eq!(holder.weighted, Uint128(1_000));
+            assert_eq!(holder.available, coins(1_500, "uusd"));
+            let holder = query_holder(&deps, before_all.default_sender_two.clone()).unwrap();
+            assert_eq!(holder.available, coins(1_500, "uusd"));
+            assert_totals_consistent(&deps);
+
+            // Settling the holder releases the expired batch
+            let mut env_other = mock_env(before_all.default_sender_owner.clone(), &[]);
+            env_other.block.height = expiry + 10_000;
+            let msg = HandleMsg::ExpireLock {
+                address: before_all.default_sender.clone(),
+            };
+            handle(&mut deps, env_other, msg).unwrap();
+            assert!(expired_lock_storage_read(&deps.storage)
+                .may_load(&expiry.to_be_bytes())
+                .unwrap()
+                .is_none());
+            assert_totals_consistent(&deps);
+        }
+    }
+
+    mod min_bonding_age {
+        use super::*;
+        // bond warming up
+        #[test]
+        fn warming_stake_earns_after_min_bonding_age() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let mut state = config(&mut deps.storage).load().unwrap();
+            state.min_bonding_age = 100;
+            config(&mut deps.storage).save(&state).unwrap();
+
+            let mut env = mock_env(before_all.default_sender.clone(), &[]);
+            let start_height = env.block.height;
+            let msg = HandleMsg::Stake {
+                amount: Uint128(1_000),
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
+            let holder = query_holder(&deps, before_all.default_sender.clone()).unwrap();
+            assert_eq!(holder.eligible, Uint128::zero());
+            assert_eq!(
+                holder.warming,
+                vec![WarmingInfo {
+                    amount: Uint128(1_000),
+                    eligible_height: start_height + 100,
+                }]
+            );
+            assert_totals_consistent(&deps);
+
+            // A payout right after staking has no eligible stake to reward
+            let mut env_payer = mock_env(
+                before_all.default_contract_address.clone(),
+                &coins(1_000, "uusd"),
+            );
+            let res = handle(&mut deps, env_payer.clone(), HandleMsg::PayoutReward {}).unwrap();
+            assert_eq!(res.messages.len(), 1);
+
+            let mut env_two = mock_env(before_all.default_sender_two.clone(), &[]);
+            env_two.block.height = start_height + 100;
+            let msg = HandleMsg::Stake {
+                amount: Uint128(1_000),
+            };
+            handle(&mut deps, env_two.clone(), msg).unwrap();
+            env_payer.block.height = start_height + 100;
+            handle(&mut deps, env_payer.clone(), HandleMsg::PayoutReward {}).unwrap();
+            let holder = query_holder(&deps, before_all.default_sender.clone()).unwrap();
+            assert_eq!(holder.eligible, Uint128(1_000));
+            assert_eq!(holder.warming, vec![]);
+            assert_eq!(holder.available, coins(1_000, "uusd"));
+            let holder = query_holder(&deps, before_all.default_sender_two.clone()).unwrap();
+            assert_eq!(holder.eligible, Uint128::zero());
+            assert_eq!(holder.available, vec![]);
+            assert_totals_consistent(&deps);
+
+            // UnStake takes warming deposits first
+            env_two.block.height = start_height + 150;
+            let msg = HandleMsg::UnStake {
+                amount: Uint128(400),
+            };
+            handle(&mut deps, env_two, msg).unwrap();
+            let holder = query_holder(&deps, before_all.default_sender_two.clone()).unwrap();
+            assert_eq!(holder.warming[0].amount, Uint128(600));
+            assert_totals_consistent(&deps);
+
+            env_payer.block.height = start_height + 200;
+            env_payer.message.sent_funds = coins(1_600, "uusd");
+            handle(&mut deps, env_payer, HandleMsg::PayoutReward {}).unwrap();
+            let holder = query_holder(&deps, before_all.default_sender.clone()).unwrap();
+            assert_eq!(holder.available, coins(2_000, "uusd"));
+            let holder = query_holder(&deps, before_all.default_sender_two.clone()).unwrap();
+            assert_eq!(holder.eligible, Uint128(600));
+            assert_eq!(holder.available, coins(600, "uusd"));
+            assert_totals_consistent(&deps);
+
+            // Matured batches are dropped once their holders settled
+            let key = (start_height + 100).to_be_bytes();
+            assert!(matured_storage_read(&deps.storage)
+                .may_load(&key)
+                .unwrap()
+                .is_some());
+            env.block.height = start_height + 250;
+            let msg = HandleMsg::Stake { amount: Uint128(1) };
+            handle(&mut deps, env, msg).unwrap();
+            assert!(matured_storage_read(&deps.storage)
+                .may_load(&key)
+                .unwrap()
+                .is_none());
+        }
+
+        #[test]
+        fn payout_matures_warming_stake() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let mut state = config(&mut deps.storage).load().unwrap();
+            state.min_bonding_age = 10;
+            config(&mut deps.storage).save(&state).unwrap();
+
+            let env = mock_env(before_all.default_sender.clone(), &[]);
+            let msg = HandleMsg::Stake {
+                amount: Uint128(1_000),
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
+
+            // No other operation matured the batch before the payout
+            let mut env_payer = mock_env(
+                before_all.default_contract_address.clone(),
+                &coins(1_000, "uusd"),
+            );
+            env_payer.block.height = env.block.height + 20;
+            let res = handle(&mut deps, env_payer, HandleMsg::PayoutReward {}).unwrap();
+            assert_eq!(res.messages.len(), 0);
+            let holder = query_holder(&deps, before_all.default_sender.clone()).unwrap();
+            assert_eq!(holder.eligible, Uint128(1_000));
+            assert_eq!(holder.available, coins(1_000, "uusd"));
+            assert_totals_consistent(&deps);
+        }
+
+        #[test]
+        fn stream_skips_warming_blocks() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(
+                before_all.default_length,
+                &[Coin {
+                    denom: "uusd".to_string(),
+                    amount: Uint128(10_000),
+                }],
+            );
+            default_init(&mut deps);
+            let mut state = config(&mut deps.storage).load().unwrap();
+            state.min_bonding_age = 10;
+            config(&mut deps.storage).save(&state).unwrap();
+
+            let mut env_two = mock_env(before_all.default_sender_two.clone(), &[]);
+            let start_height = env_two.block.height;
+            let msg = HandleMsg::Stake {
+                amount: Uint128(1_000),
+            };
+            handle(&mut deps, env_two.clone(), msg.clone()).unwrap();
+
+            // The second deposit warms up during the first 10 blocks of the stream
+            let mut env = mock_env(before_all.default_sender.clone(), &[]);
+            env.block.height = start_height + 100;
+            handle(&mut deps, env, msg).unwrap();
+            let mut env_admin = mock_env(
+                before_all.default_sender_owner.clone(),
+                &coins(1_000, "uusd"),
+            );
+            env_admin.block.height = start_height + 100;
+            let msg = HandleMsg::PayoutRewardStream {
+                start_height: start_height + 100,
+                end_height: start_height + 200,
+            };
+            handle(&mut deps, env_admin, msg).unwrap();
+
+            // Nobody acts until the stream ends
+            env_two.block.height = start_height + 200;
+            let res = handle(&mut deps, env_two, HandleMsg::ClaimReward {}).unwrap();
+            assert_eq!(
+                res.messages[0],
+                CosmosMsg::Bank(BankMsg::Send {
+                    from_address: HumanAddr::from("cosmos2contract"),
+                    to_address: before_all.default_sender_two.clone(),
+                    amount: coins(550, "uusd"),
+                })
+            );
+            let holder = query_holder(&deps, before_all.default_sender.clone()).unwrap();
+            assert_eq!(holder.available, coins(450, "uusd"));
+            assert_totals_consistent(&deps);
+        }
+    }
+
+    mod instant_unstake {
+        use super::*;
+        // handle_instant_unstake
+        fn stake_and_set_penalty<S: Storage, A: Api, Q: Querier>(
+            deps: &mut Extern<S, A, Q>,
+            destination: PenaltyDestination,
+        ) {
+            let before_all = before_all();
+            default_init(deps);
+            let msg = HandleMsg::Stake {
+                amount: Uint128(1_000),
+            };
+            let env = mock_env(before_all.default_sender.clone(), &[]);
+            handle(deps, env, msg.clone()).unwrap();
+            let env = mock_env(before_all.default_sender_two.clone(), &[]);
+            handle(deps, env, msg).unwrap();
+            let env = mock_env(before_all.default_sender_owner.clone(), &[]);
+            let msg = HandleMsg::SetInstantUnStakePenalty {
+                rate: Decimal::percent(10),
+                destination,
+                treasury: Some(before_all.default_contract_address_two),
+            };
+            handle(deps, env, msg).unwrap();
+        }
+
+        #[test]
+        fn set_penalty() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let msg = HandleMsg::SetInstantUnStakePenalty {
+                rate: Decimal::percent(10),
+                destination: PenaltyDestination::Treasury,
+                treasury: None,
+            };
+            let env = mock_env(before_all.default_sender.clone(), &[]);
+            let res = handle(&mut deps, env, msg.clone());
+            match res {
+                Err(StdError::Unauthorized { .. }) => {}
+                _ => panic!("Unexpected error"),
+            }
+            let env = mock_env(before_all.default_sender_owner.clone(), &[]);
+            let res = handle(&mut deps, env.clone(), msg);
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, "Treasury address required");
+                }
+                _ => panic!("Unexpected error"),
+            }
+            let msg = HandleMsg::SetInstantUnStakePenalty {
+                rate: Decimal::percent(101),
+                destination: PenaltyDestination::Burn,
+                treasury: None,
+            };
+            let res = handle(&mut deps, env.clone(), msg);
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, "Penalty rate can't exceed 100%");
+                }
+                _ => panic!("Unexpected error"),
+            }
+            let msg = HandleMsg::SetInstantUnStakePenalty {
+                rate: Decimal::percent(5),
+                destination: PenaltyDestination::Burn,
+                treasury: None,
+            };
+            handle(&mut deps, env, msg).unwrap();
+            let state = query_config(&deps).unwrap();
+            assert_eq!(state.instant_unstake_penalty, Decimal::percent(5));
+            assert_eq!(state.penalty_destination, PenaltyDestination::Burn);
+        }
+
+        #[test]
+        fn penalty_burned() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            stake_and_set_penalty(&mut deps, PenaltyDestination::Burn);
+            let env = mock_env(before_all.default_sender.clone(), &[]);
+            let msg = HandleMsg::InstantUnStake {
+                amount: Uint128(500),
+            };
+            let res = handle(&mut deps, env, msg).unwrap();
+            assert_eq!(
+                res.messages,
+                vec![
+                    encode_msg_execute(
+                        Cw20ExecuteMsg::Transfer {
+                            recipient: before_all.default_sender.clone(),
+                            amount: Uint128(450),
+                        },
+                        before_all.default_contract_address.clone(),
+                    )
+                    .unwrap(),
+                    encode_msg_execute(
+                        Cw20ExecuteMsg::Burn {
+                            amount: Uint128(50),
+                        },
+                        before_all.default_contract_address,
+                    )
+                    .unwrap(),
+                ]
+            );
+            let holder = query_holder(&deps, before_all.default_sender).unwrap();
+            assert_eq!(holder.bonded, Uint128(500));
+            assert_eq!(holder.un_bonded, Uint128::zero());
+            assert_totals_consistent(&deps);
+        }
+
+        #[test]
+        fn penalty_to_treasury() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            stake_and_set_penalty(&mut deps, PenaltyDestination::Treasury);
+            let env = mock_env(before_all.default_sender.clone(), &[]);
+            let msg = HandleMsg::InstantUnStake {
+                amount: Uint128(500),
+            };
+            let res = handle(&mut deps, env, msg).unwrap();
+            assert_eq!(
+                res.messages[1],
+                encode_msg_execute(
+                    Cw20ExecuteMsg::Transfer {
+                        recipient: before_all.default_contract_address_two,
+                        amount: Uint128(50),
+                    },
+                    before_all.default_contract_address,
+                )
+                .unwrap()
+            );
+        }
+
+        #[test]
+        fn penalty_redistributed() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            stake_and_set_penalty(&mut deps, PenaltyDestination::Redistribute);
+            let env = mock_env(before_all.default_sender.clone(), &[]);
+            let msg = HandleMsg::InstantUnStake {
+                amount: Uint128(1_000),
+            };
+            let res = handle(&mut deps, env, msg).unwrap();
+            assert_eq!(res.messages.len(), 1);
+            assert_eq!(
+                res.log[2],
+                LogAttribute {
+                    key: "penalty".to_string(),
+                    value: "100".to_string(),
+                }
+            );
+            // The remaining staker gets the whole penalty in LOTA
+            let holder = query_holder(&deps, before_all.default_sender.clone()).unwrap();
+            assert!(holder.available_tokens.is_empty());
+            let holder = query_holder(&deps, before_all.default_sender_two).unwrap();
+            assert_eq!(
+                holder.available_tokens,
+                vec![TokenBalanceResponse {
+                    token: before_all.default_contract_address,
+                    amount: Uint128(100),
+                }]
+            );
+            assert_totals_consistent(&deps);
+        }
     }
-    mod safe_lock {
-        use super::*;
-        // handle_switch
 
+    mod rebond {
+        use super::*;
+        // handle_rebond
         #[test]
-        fn only_admin() {
+        fn rebond_more_than_unbonding() {
             let before_all = before_all();
             let mut deps = mock_dependencies(before_all.default_length, &[]);
             default_init(&mut deps);
-            let env = mock_env(before_all.default_sender_two, &[]);
+            let env = mock_env(before_all.default_sender.clone(), &[]);
+            let msg = HandleMsg::Stake {
+                amount: Uint128(1_000),
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
+            let msg = HandleMsg::UnStake {
+                amount: Uint128(300),
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
 
-            let res = handle_safe_lock(&mut deps, env);
+            let msg = HandleMsg::ReBond {
+                amount: Uint128(301),
+            };
+            let res = handle(&mut deps, env, msg);
             match res {
-                Err(StdError::Unauthorized { .. }) => {}
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, "You can't reBond more than you have unBonding (300)");
+                }
                 _ => panic!("Unexpected error"),
             }
         }
+
         #[test]
         fn success() {
             let before_all = before_all();
             let mut deps = mock_dependencies(before_all.default_length, &[]);
             default_init(&mut deps);
-            let env = mock_env(before_all.default_sender_owner, &[]);
+            let mut env = mock_env(before_all.default_sender.clone(), &[]);
+            let msg = HandleMsg::Stake {
+                amount: Uint128(1_000),
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
+            let msg = HandleMsg::UnStake {
+                amount: Uint128(300),
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
+            env.block.height += 10;
+            let msg = HandleMsg::UnStake {
+                amount: Uint128(200),
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
 
-            // Switch to Off
-            let res = handle_safe_lock(&mut deps, env.clone()).unwrap();
+            let msg = HandleMsg::ReBond {
+                amount: Uint128(250),
+            };
+            let res = handle(&mut deps, env.clone(), msg).unwrap();
             assert_eq!(res.messages.len(), 0);
-            let state = config(&mut deps.storage).load().unwrap();
-            assert!(state.safe_lock);
-            // Switch to On
-            let res = handle_safe_lock(&mut deps, env).unwrap();
-            println!("{:?}", res);
-            let state = config(&mut deps.storage).load().unwrap();
-            assert!(!state.safe_lock);
+            assert_eq!(
+                res.log[0],
+                LogAttribute {
+                    key: "action".to_string(),
+                    value: "rebond lota".to_string(),
+                }
+            );
+            let holder = query_holder(&deps, before_all.default_sender.clone()).unwrap();
+            assert_eq!(holder.bonded, Uint128(750));
+            assert_eq!(holder.un_bonded, Uint128(250));
+            assert_eq!(
+                holder.unbonding,
+                vec![UnbondingInfo {
+                    amount: Uint128(250),
+                    release_height: env.block.height - 10 + 100,
+                }]
+            );
+            let all = query_all_bonded(&deps).unwrap();
+            assert_eq!(all.total_bonded, Uint128(750));
+            assert_eq!(all.total_unbonding, Uint128(250));
+            assert_totals_consistent(&deps);
         }
     }
-    mod renounce {
+
+    mod emergency_withdraw {
         use super::*;
-        // handle_renounce
+        // handle_emergency_withdraw
+
         #[test]
-        fn only_admin() {
+        fn not_active() {
             let before_all = before_all();
             let mut deps = mock_dependencies(before_all.default_length, &[]);
             default_init(&mut deps);
-            let env = mock_env(before_all.default_sender_two, &[]);
+            let env = mock_env(before_all.default_sender.clone(), &[]);
+            let msg = HandleMsg::Stake {
+                amount: Uint128(2_000),
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
+            // Pausing only stake and payout doesn't lock withdrawals
+            let owner_env = mock_env(before_all.default_sender_owner.clone(), &[]);
+            let msg = HandleMsg::SetPauseFlags {
+                flags: PauseFlags {
+                    stake: true,
+                    payout: true,
+                    ..PauseFlags::default()
+                },
+            };
+            handle(&mut deps, owner_env, msg).unwrap();
 
-            let res = handle_renounce(&mut deps, env);
+            let res = handle(&mut deps, env, HandleMsg::EmergencyWithdraw {});
             match res {
-                Err(StdError::Unauthorized { .. }) => {}
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, "Emergency withdrawal not active");
+                }
                 _ => panic!("Unexpected error"),
             }
+            let status = query_emergency_status(&deps).unwrap();
+            assert_eq!(status.locked_since, None);
+            assert_eq!(status.withdrawal_height, None);
         }
+
         #[test]
-        fn safe_lock_on() {
+        fn delay_not_passed() {
             let before_all = before_all();
             let mut deps = mock_dependencies(before_all.default_length, &[]);
             default_init(&mut deps);
-            let env = mock_env(before_all.default_sender_owner, &[]);
+            let mut env = mock_env(before_all.default_sender.clone(), &[]);
+            let msg = HandleMsg::Stake {
+                amount: Uint128(2_000),
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
+            let owner_env = mock_env(before_all.default_sender_owner.clone(), &[]);
+            handle(&mut deps, owner_env.clone(), HandleMsg::SafeLock {}).unwrap();
 
-            let mut state = config(&mut deps.storage).load().unwrap();
-            state.safe_lock = true;
-            config(&mut deps.storage).save(&state).unwrap();
+            let status = query_emergency_status(&deps).unwrap();
+            assert_eq!(status.locked_since, Some(env.block.height));
+            assert_eq!(status.emergency_withdraw_delay, 1_000);
+            assert_eq!(status.withdrawal_height, Some(env.block.height + 1_000));
 
-            let res = handle_renounce(&mut deps, env);
+            // Changing which withdrawals are paused keeps the lock start
+            let mut later_env = owner_env.clone();
+            later_env.block.height += 500;
+            let msg = HandleMsg::SetPauseFlags {
+                flags: PauseFlags {
+                    claim_reward: true,
+                    ..PauseFlags::default()
+                },
+            };
+            handle(&mut deps, later_env, msg).unwrap();
+            let status = query_emergency_status(&deps).unwrap();
+            assert_eq!(status.locked_since, Some(env.block.height));
+
+            env.block.height += 999;
+            let res = handle(&mut deps, env, HandleMsg::EmergencyWithdraw {});
             match res {
                 Err(GenericErr {
                     msg,
                     backtrace: None,
                 }) => {
-                    assert_eq!(msg, "Contract is locked");
+                    assert_eq!(msg, "Emergency withdrawal available from block 13345");
                 }
                 _ => panic!("Unexpected error"),
             }
+
+            // Resuming withdrawals resets the lock
+            let msg = HandleMsg::SetPauseFlags {
+                flags: PauseFlags::default(),
+            };
+            handle(&mut deps, owner_env, msg).unwrap();
+            let status = query_emergency_status(&deps).unwrap();
+            assert_eq!(status.locked_since, None);
         }
+
         #[test]
-        fn success() {
+        fn lock_again_after_lapse() {
             let before_all = before_all();
             let mut deps = mock_dependencies(before_all.default_length, &[]);
             default_init(&mut deps);
-            let env = mock_env(before_all.default_sender_owner.clone(), &[]);
+            let mut state = config(&mut deps.storage).load().unwrap();
+            state.max_pause_duration = 500;
+            config(&mut deps.storage).save(&state).unwrap();
+            let mut env = mock_env(before_all.default_sender.clone(), &[]);
+            let msg = HandleMsg::Stake {
+                amount: Uint128(2_000),
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
+            let start = env.block.height;
+            let mut owner_env = mock_env(before_all.default_sender_owner.clone(), &[]);
+            handle(&mut deps, owner_env.clone(), HandleMsg::SafeLock {}).unwrap();
 
-            // Transfer power to admin
-            let res = handle_renounce(&mut deps, env.clone()).unwrap();
-            assert_eq!(res.messages.len(), 0);
-            let state = config(&mut deps.storage).load().unwrap();
-            assert_ne!(
-                state.admin,
-                deps.api
-                    .canonical_address(&before_all.default_sender_owner)
-                    .unwrap()
+            // The pause lapses before the emergency withdraw delay
+            let status = query_emergency_status(&deps).unwrap();
+            assert_eq!(status.locked_since, Some(start));
+            assert_eq!(status.withdrawal_height, None);
+
+            // Locked again 100 blocks after the lapse, those blocks don't count
+            owner_env.block.height = start + 600;
+            handle(&mut deps, owner_env.clone(), HandleMsg::SafeLock {}).unwrap();
+            let status = query_emergency_status(&deps).unwrap();
+            assert_eq!(status.locked_since, Some(start + 100));
+            assert_eq!(status.withdrawal_height, None);
+            owner_env.block.height = start + 1_000;
+            handle(&mut deps, owner_env.clone(), HandleMsg::ExtendPause {}).unwrap();
+            let status = query_emergency_status(&deps).unwrap();
+            assert_eq!(status.withdrawal_height, Some(start + 1_100));
+
+            env.block.height = start + 1_100;
+            handle(&mut deps, env, HandleMsg::EmergencyWithdraw {}).unwrap();
+            assert!(query_holder(&deps, before_all.default_sender.clone()).is_err());
+
+            // Locked again long after the lapse at start + 1_500, the clock starts over
+            owner_env.block.height = start + 2_500;
+            handle(&mut deps, owner_env, HandleMsg::SafeLock {}).unwrap();
+            let status = query_emergency_status(&deps).unwrap();
+            assert_eq!(status.locked_since, Some(start + 2_500));
+        }
+
+        #[test]
+        fn success() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(
+                before_all.default_length,
+                &[Coin {
+                    denom: "uusd".to_string(),
+                    amount: Uint128(10_000),
+                }],
+            );
+            default_init(&mut deps);
+            let mut env = mock_env(before_all.default_sender.clone(), &[]);
+            let msg = HandleMsg::Stake {
+                amount: Uint128(2_000),
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
+            let msg = HandleMsg::UnStake {
+                amount: Uint128(500),
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
+            let env_two = mock_env(before_all.default_sender_two.clone(), &[]);
+            let msg = HandleMsg::Stake {
+                amount: Uint128(1_500),
+            };
+            handle(&mut deps, env_two, msg).unwrap();
+            let payout_env = mock_env(
+                before_all.default_sender_owner.clone(),
+                &[Coin {
+                    denom: "uusd".to_string(),
+                    amount: Uint128(3_000),
+                }],
+            );
+            handle(&mut deps, payout_env, HandleMsg::PayoutReward {}).unwrap();
+            let owner_env = mock_env(before_all.default_sender_owner.clone(), &[]);
+            handle(&mut deps, owner_env, HandleMsg::SafeLock {}).unwrap();
+
+            env.block.height += 1_000;
+            let res = handle(&mut deps, env.clone(), HandleMsg::EmergencyWithdraw {}).unwrap();
+            assert_eq!(res.messages.len(), 2);
+            let msg = Cw20ExecuteMsg::Transfer {
+                recipient: before_all.default_sender.clone(),
+                amount: Uint128(2_000),
+            };
+            assert_eq!(
+                res.messages[0],
+                encode_msg_execute(msg, before_all.default_contract_address.clone()).unwrap()
             );
             assert_eq!(
-                state.admin,
-                deps.api.canonical_address(&env.contract.address).unwrap()
+                res.messages[1],
+                CosmosMsg::Bank(BankMsg::Send {
+                    from_address: env.contract.address.clone(),
+                    to_address: before_all.default_sender.clone(),
+                    amount: vec![Coin {
+                        denom: "uusd".to_string(),
+                        amount: Uint128(1_500),
+                    }],
+                })
             );
+
+            let state = config_read(&deps.storage).load().unwrap();
+            assert_eq!(state.total_bonded, Uint128(1_500));
+            assert_eq!(state.total_weighted, Uint128(1_500));
+            assert_eq!(state.total_unbonding, Uint128::zero());
+            assert!(query_holder(&deps, before_all.default_sender.clone()).is_err());
+            assert_totals_consistent(&deps);
+
+            let res = handle(&mut deps, env, HandleMsg::EmergencyWithdraw {});
+            assert!(res.is_err());
         }
     }
-
-    mod stake {
+    mod governance {
         use super::*;
-        // handle_stake
+        // handle_create_proposal, handle_cast_vote, handle_end_proposal, handle_execute_proposal
+
+        fn stake<S: Storage, A: Api, Q: Querier>(
+            deps: &mut Extern<S, A, Q>,
+            sender: HumanAddr,
+            amount: u128,
+            height: u64,
+        ) {
+            let mut env = mock_env(sender, &[]);
+            env.block.height = height;
+            let msg = HandleMsg::Stake {
+                amount: Uint128(amount),
+            };
+            handle(deps, env, msg).unwrap();
+        }
+
+        fn update_unbonded_period(unbonded_period: u64) -> HandleMsg {
+            HandleMsg::UpdateConfig {
+                unbonded_period: Some(unbonded_period),
+                reward_denoms: None,
+                reward_tokens: None,
+                address_cw20_loterra_smart_contract: None,
+                max_unbonding_entries: None,
+                carry_reward_remainder: None,
+                lock_tiers: None,
+                min_bonding_age: None,
+                timelock_delay: None,
+                emergency_withdraw_delay: None,
+                max_pause_duration: None,
+                governance: None,
+            }
+        }
+
+        fn vote<S: Storage, A: Api, Q: Querier>(
+            deps: &mut Extern<S, A, Q>,
+            sender: HumanAddr,
+            option: VoteOption,
+            height: u64,
+        ) -> StdResult<HandleResponse> {
+            let mut env = mock_env(sender, &[]);
+            env.block.height = height;
+            let msg = HandleMsg::CastVote {
+                proposal_id: 0,
+                option,
+            };
+            handle(deps, env, msg)
+        }
+
         #[test]
-        fn do_not_send_funds() {
+        fn create_proposal() {
             let before_all = before_all();
             let mut deps = mock_dependencies(before_all.default_length, &[]);
             default_init(&mut deps);
-            let env = mock_env(
-                before_all.default_sender_owner.clone(),
-                &[Coin {
-                    denom: "x".to_string(),
-                    amount: Uint128(2_000),
-                }],
+            let env = mock_env(before_all.default_sender.clone(), &[]);
+            stake(
+                &mut deps,
+                before_all.default_sender.clone(),
+                999,
+                env.block.height,
             );
-            let msg = HandleMsg::Stake {
-                amount: Uint128(2_000),
+            let msg = HandleMsg::CreateProposal {
+                description: "Longer unBonding".to_string(),
+                msg: Some(Box::new(update_unbonded_period(200))),
             };
             let res = handle(&mut deps, env.clone(), msg.clone());
             match res {
@@ -730,215 +6813,412 @@ mod tests {
                     msg,
                     backtrace: None,
                 }) => {
-                    assert_eq!(msg, "Do not send funds with stake");
+                    assert_eq!(msg, "You need 1000 bonded to create a proposal");
                 }
                 _ => panic!("Unexpected error"),
             }
-        }
-        #[test]
-        fn amount_required(){
-            let before_all = before_all();
-            let mut deps = mock_dependencies(before_all.default_length, &[]);
-            default_init(&mut deps);
-            let env = mock_env(
-                before_all.default_sender_owner.clone(),
-                &[],
+
+            stake(
+                &mut deps,
+                before_all.default_sender.clone(),
+                1,
+                env.block.height,
             );
-            let msg = HandleMsg::Stake {
-                amount: Uint128(0),
+            let renounce = HandleMsg::CreateProposal {
+                description: "Renounce".to_string(),
+                msg: Some(Box::new(HandleMsg::Renounce {})),
             };
-            let res = handle(&mut deps, env.clone(), msg.clone());
+            let res = handle(&mut deps, env.clone(), renounce);
             match res {
                 Err(GenericErr {
-                        msg,
-                        backtrace: None,
-                    }) => {
-                    assert_eq!(msg, "Amount required");
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, "Proposals can only change the config");
                 }
                 _ => panic!("Unexpected error"),
             }
-        }
-        #[test]
-        fn success() {
-            let before_all = before_all();
-            let mut deps = mock_dependencies(before_all.default_length, &[]);
-            default_init(&mut deps);
-            let env = mock_env(before_all.default_sender_owner.clone(), &[]);
-            let msg = HandleMsg::Stake {
-                amount: Uint128(2_000),
+            let empty = HandleMsg::CreateProposal {
+                description: "".to_string(),
+                msg: None,
             };
-            let res = handle(&mut deps, env.clone(), msg.clone()).unwrap();
-            assert_eq!(res.messages.len(), 1);
-            assert_eq!(
-                res.messages[0],
-                CosmosMsg::Wasm(WasmMsg::Execute {
-                    contract_addr: before_all.default_contract_address,
-                    msg: Binary::from(r#"{"transfer_from":{"owner":"terra1q88h7ewu6h3am4mxxeqhu3srt7zw4z5s20qu3k","recipient":"cosmos2contract","amount":"2000"}}"#.as_bytes()),
-                    send: vec![]
-                })
-            );
-            let store = staking_storage(&mut deps.storage)
-                .load(
-                    &deps
-                        .api
-                        .canonical_address(&before_all.default_sender_owner)
-                        .unwrap()
-                        .as_slice(),
-                )
-                .unwrap();
-            assert_eq!(store.bonded, Uint128(2_000));
-            assert_eq!(store.un_bonded, Uint128::zero());
-            assert_eq!(store.available, Uint128::zero());
-            assert_eq!(store.period, 0);
-
-            // Stake more
-            let res = handle(&mut deps, env.clone(), msg.clone()).unwrap();
-            let store = staking_storage(&mut deps.storage)
-                .load(
-                    &deps
-                        .api
-                        .canonical_address(&before_all.default_sender_owner)
-                        .unwrap()
-                        .as_slice(),
-                )
-                .unwrap();
-            assert_eq!(store.bonded, Uint128(4_000));
-            assert_eq!(store.un_bonded, Uint128::zero());
-            assert_eq!(store.available, Uint128::zero());
-            assert_eq!(store.period, 0);
+            assert!(handle(&mut deps, env.clone(), empty).is_err());
 
-            let all_bonded = query_all_bonded(&deps).unwrap();
-            assert_eq!(all_bonded.total_bonded, Uint128(4_000));
+            handle(&mut deps, env.clone(), msg).unwrap();
+            let proposal = query_proposal(&deps, 0).unwrap();
+            assert_eq!(proposal.start_height, env.block.height);
+            assert_eq!(proposal.end_height, env.block.height + 100);
+            assert_eq!(proposal.status, ProposalStatus::Open);
+            let state = config_read(&deps.storage).load().unwrap();
+            assert_eq!(state.next_proposal_id, 1);
         }
-    }
-    mod unstake {
-        use super::*;
-        // handle_unstake
+
         #[test]
-        fn do_not_send_funds() {
+        fn cast_vote() {
             let before_all = before_all();
             let mut deps = mock_dependencies(before_all.default_length, &[]);
             default_init(&mut deps);
-            let env = mock_env(
+            let env = mock_env(before_all.default_sender.clone(), &[]);
+            let start = env.block.height;
+            stake(&mut deps, before_all.default_sender.clone(), 3_000, start);
+            let msg = HandleMsg::CreateProposal {
+                description: "Text".to_string(),
+                msg: None,
+            };
+            handle(&mut deps, env, msg).unwrap();
+
+            let res = vote(
+                &mut deps,
+                before_all.default_sender.clone(),
+                VoteOption::Yes,
+                start,
+            );
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, format!("Voting starts at block {}", start + 1));
+                }
+                _ => panic!("Unexpected error"),
+            }
+            vote(
+                &mut deps,
+                before_all.default_sender.clone(),
+                VoteOption::Yes,
+                start + 1,
+            )
+            .unwrap();
+            let res = vote(
+                &mut deps,
                 before_all.default_sender.clone(),
-                &[Coin {
-                    denom: "x".to_string(),
-                    amount: Uint128(2_000),
-                }],
+                VoteOption::No,
+                start + 2,
+            );
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, "Already voted");
+                }
+                _ => panic!("Unexpected error"),
+            }
+
+            // Tokens staked after the start height carry no power
+            stake(
+                &mut deps,
+                before_all.default_sender_two.clone(),
+                5_000,
+                start + 1,
             );
+            let res = vote(
+                &mut deps,
+                before_all.default_sender_two.clone(),
+                VoteOption::No,
+                start + 2,
+            );
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, format!("No voting power at block {}", start));
+                }
+                _ => panic!("Unexpected error"),
+            }
+
+            // UnStaking after voting doesn't change the tally
+            let mut env = mock_env(before_all.default_sender.clone(), &[]);
+            env.block.height = start + 3;
             let msg = HandleMsg::UnStake {
-                amount: Uint128(2_000),
+                amount: Uint128(3_000),
             };
-            let res = handle(&mut deps, env.clone(), msg.clone());
+            handle(&mut deps, env, msg).unwrap();
+            let proposal = query_proposal(&deps, 0).unwrap();
+            assert_eq!(proposal.yes, Uint128(3_000));
+            assert_eq!(proposal.no, Uint128::zero());
+            let res = query_vote(&deps, 0, before_all.default_sender.clone()).unwrap();
+            assert_eq!(
+                res.vote,
+                Some(Vote {
+                    option: VoteOption::Yes,
+                    power: Uint128(3_000),
+                })
+            );
+
+            let res = vote(
+                &mut deps,
+                before_all.default_sender_owner.clone(),
+                VoteOption::No,
+                start + 101,
+            );
             match res {
                 Err(GenericErr {
                     msg,
                     backtrace: None,
                 }) => {
-                    assert_eq!(msg, "Do not send funds with un_stake");
+                    assert_eq!(msg, format!("Voting ended at block {}", start + 100));
                 }
                 _ => panic!("Unexpected error"),
             }
         }
+
         #[test]
-        fn no_stake_registered_from_this_address() {
+        fn passed_proposal_executed_by_anyone() {
             let before_all = before_all();
             let mut deps = mock_dependencies(before_all.default_length, &[]);
             default_init(&mut deps);
-            let env = mock_env(before_all.default_sender.clone(), &[]);
-            let msg = HandleMsg::UnStake {
-                amount: Uint128(2_000),
+            let mut env = mock_env(before_all.default_sender.clone(), &[]);
+            let start = env.block.height;
+            stake(&mut deps, before_all.default_sender.clone(), 3_000, start);
+            stake(
+                &mut deps,
+                before_all.default_sender_two.clone(),
+                2_000,
+                start,
+            );
+            let msg = HandleMsg::CreateProposal {
+                description: "Longer unBonding".to_string(),
+                msg: Some(Box::new(update_unbonded_period(200))),
             };
-            let res = handle(&mut deps, env.clone(), msg.clone());
-            println!("{:?}", res);
+            handle(&mut deps, env.clone(), msg).unwrap();
+            vote(
+                &mut deps,
+                before_all.default_sender.clone(),
+                VoteOption::Yes,
+                start + 1,
+            )
+            .unwrap();
+            vote(
+                &mut deps,
+                before_all.default_sender_two.clone(),
+                VoteOption::No,
+                start + 1,
+            )
+            .unwrap();
+
+            // The admin renounced, stakers still decide
+            let owner_env = mock_env(before_all.default_sender_owner.clone(), &[]);
+            handle(&mut deps, owner_env, HandleMsg::Renounce {}).unwrap();
+
+            env.block.height = start + 100;
+            let res = handle(
+                &mut deps,
+                env.clone(),
+                HandleMsg::ExecuteProposal { proposal_id: 0 },
+            );
             match res {
-                Err(StdError::Unauthorized { .. }) => {}
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, "Proposal 0 has not passed");
+                }
+                _ => panic!("Unexpected error"),
+            }
+            let res = handle(
+                &mut deps,
+                env.clone(),
+                HandleMsg::EndProposal { proposal_id: 0 },
+            );
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, format!("Voting ends at block {}", start + 100));
+                }
                 _ => panic!("Unexpected error"),
             }
+
+            env.block.height = start + 101;
+            handle(
+                &mut deps,
+                env.clone(),
+                HandleMsg::EndProposal { proposal_id: 0 },
+            )
+            .unwrap();
+            let proposal = query_proposal(&deps, 0).unwrap();
+            assert_eq!(proposal.status, ProposalStatus::Passed);
+            assert_eq!(proposal.total_power, Uint128(5_000));
+
+            let env = mock_env(before_all.default_sender_owner.clone(), &[]);
+            let res = handle(
+                &mut deps,
+                env.clone(),
+                HandleMsg::ExecuteProposal { proposal_id: 0 },
+            )
+            .unwrap();
+            assert_eq!(res.log[0].value, "0");
+            let state = config_read(&deps.storage).load().unwrap();
+            assert_eq!(state.unbonded_period, 200);
+            let proposal = query_proposal(&deps, 0).unwrap();
+            assert_eq!(proposal.status, ProposalStatus::Executed);
+            let res = handle(
+                &mut deps,
+                env,
+                HandleMsg::ExecuteProposal { proposal_id: 0 },
+            );
+            assert!(res.is_err());
         }
+
         #[test]
-        fn unstake_more_than_staked() {
+        fn rejected_without_quorum() {
             let before_all = before_all();
             let mut deps = mock_dependencies(before_all.default_length, &[]);
             default_init(&mut deps);
-            let env = mock_env(before_all.default_sender.clone(), &[]);
-            // Stake some funds
-            let msg = HandleMsg::Stake {
-                amount: Uint128(2_000),
-            };
-            let res = handle(&mut deps, env.clone(), msg.clone()).unwrap();
-            // UnStake some funds
-            let msg = HandleMsg::UnStake {
-                amount: Uint128(3_000),
+            let mut env = mock_env(before_all.default_sender.clone(), &[]);
+            let start = env.block.height;
+            stake(&mut deps, before_all.default_sender.clone(), 1_000, start);
+            stake(
+                &mut deps,
+                before_all.default_sender_two.clone(),
+                9_000,
+                start,
+            );
+            let msg = HandleMsg::CreateProposal {
+                description: "Longer unBonding".to_string(),
+                msg: Some(Box::new(update_unbonded_period(200))),
             };
-            let res = handle(&mut deps, env.clone(), msg.clone());
+            handle(&mut deps, env.clone(), msg).unwrap();
+            vote(
+                &mut deps,
+                before_all.default_sender.clone(),
+                VoteOption::Yes,
+                start + 1,
+            )
+            .unwrap();
+
+            env.block.height = start + 101;
+            handle(
+                &mut deps,
+                env.clone(),
+                HandleMsg::EndProposal { proposal_id: 0 },
+            )
+            .unwrap();
+            let proposal = query_proposal(&deps, 0).unwrap();
+            assert_eq!(proposal.status, ProposalStatus::Rejected);
+            let res = handle(&mut deps, env, HandleMsg::EndProposal { proposal_id: 0 });
             match res {
                 Err(GenericErr {
                     msg,
                     backtrace: None,
                 }) => {
-                    assert_eq!(msg, "You can\'t unStake more than you have (2000)");
+                    assert_eq!(msg, "Proposal 0 already ended");
                 }
                 _ => panic!("Unexpected error"),
             }
         }
+    }
+    mod voting_power {
+        use super::*;
+        // query_voting_power_at
+
         #[test]
-        fn amount_required(){
+        fn checkpoints() {
             let before_all = before_all();
             let mut deps = mock_dependencies(before_all.default_length, &[]);
             default_init(&mut deps);
-            let env = mock_env(
-                before_all.default_sender.clone(),
-                &[],
-            );
+            let mut env = mock_env(before_all.default_sender.clone(), &[]);
+            let start = env.block.height;
+            let msg = HandleMsg::Stake {
+                amount: Uint128(1_000),
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
+            let mut env_two = mock_env(before_all.default_sender_two.clone(), &[]);
+            env_two.block.height = start + 5;
+            let msg = HandleMsg::Stake {
+                amount: Uint128(2_000),
+            };
+            handle(&mut deps, env_two, msg).unwrap();
+            env.block.height = start + 10;
+            let msg = HandleMsg::Stake {
+                amount: Uint128(500),
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
+            env.block.height = start + 20;
             let msg = HandleMsg::UnStake {
-                amount: Uint128(0),
+                amount: Uint128(300),
             };
-            let res = handle(&mut deps, env.clone(), msg.clone());
-            match res {
-                Err(GenericErr {
-                        msg,
-                        backtrace: None,
-                    }) => {
-                    assert_eq!(msg, "Amount required");
+            handle(&mut deps, env.clone(), msg).unwrap();
+
+            let power = |deps: &Extern<_, _, _>, height: u64| {
+                query_voting_power_at(deps, before_all.default_sender.clone(), height)
+                    .unwrap()
+                    .power
+            };
+            assert_eq!(power(&deps, start - 1), Uint128::zero());
+            assert_eq!(power(&deps, start), Uint128(1_000));
+            assert_eq!(power(&deps, start + 9), Uint128(1_000));
+            assert_eq!(power(&deps, start + 10), Uint128(1_500));
+            assert_eq!(power(&deps, start + 25), Uint128(1_200));
+
+            let total = |deps: &Extern<_, _, _>, height: u64| {
+                query_total_voting_power_at(deps, height).unwrap().power
+            };
+            assert_eq!(total(&deps, start - 1), Uint128::zero());
+            assert_eq!(total(&deps, start + 4), Uint128(1_000));
+            assert_eq!(total(&deps, start + 5), Uint128(3_000));
+            assert_eq!(total(&deps, start + 20), Uint128(3_200));
+            assert_eq!(total(&deps, u64::MAX), Uint128(3_200));
+
+            let res = query(
+                &deps,
+                QueryMsg::VotingPowerAt {
+                    address: before_all.default_sender_two.clone(),
+                    height: start + 5,
+                },
+            )
+            .unwrap();
+            let res: VotingPowerResponse = from_binary(&res).unwrap();
+            assert_eq!(
+                res,
+                VotingPowerResponse {
+                    height: start + 5,
+                    power: Uint128(2_000),
                 }
-                _ => panic!("Unexpected error"),
-            }
+            );
         }
+
         #[test]
-        fn success() {
+        fn rebond_and_emergency_withdraw() {
             let before_all = before_all();
             let mut deps = mock_dependencies(before_all.default_length, &[]);
             default_init(&mut deps);
-            let env = mock_env(before_all.default_sender.clone(), &[]);
-            // Stake some funds
+            let mut env = mock_env(before_all.default_sender.clone(), &[]);
+            let start = env.block.height;
             let msg = HandleMsg::Stake {
-                amount: Uint128(2_000),
+                amount: Uint128(1_000),
             };
-            let res = handle(&mut deps, env.clone(), msg.clone()).unwrap();
-            // UnStake some funds
+            handle(&mut deps, env.clone(), msg).unwrap();
             let msg = HandleMsg::UnStake {
-                amount: Uint128(1_500),
+                amount: Uint128(400),
             };
-            let res = handle(&mut deps, env.clone(), msg.clone()).unwrap();
-            assert_eq!(res.messages.len(), 0);
-            let state = config(&mut deps.storage).load().unwrap();
-            let store = staking_storage(&mut deps.storage)
-                .load(
-                    &deps
-                        .api
-                        .canonical_address(&before_all.default_sender)
-                        .unwrap()
-                        .as_slice(),
-                )
-                .unwrap();
-            assert_eq!(store.bonded, Uint128(500));
-            assert_eq!(store.un_bonded, Uint128(1_500));
-            assert_eq!(store.available, Uint128::zero());
-            assert_eq!(store.period, env.block.height + state.unbonded_period);
+            handle(&mut deps, env.clone(), msg).unwrap();
+            env.block.height = start + 1;
+            let msg = HandleMsg::ReBond {
+                amount: Uint128(100),
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
+            let owner_env = mock_env(before_all.default_sender_owner.clone(), &[]);
+            handle(&mut deps, owner_env, HandleMsg::SafeLock {}).unwrap();
+            env.block.height = start + 1_000;
+            handle(&mut deps, env, HandleMsg::EmergencyWithdraw {}).unwrap();
 
-            let all_bonded = query_all_bonded(&deps).unwrap();
-            assert_eq!(all_bonded.total_bonded, Uint128(500));
+            let power = |height: u64| {
+                query_voting_power_at(&deps, before_all.default_sender.clone(), height)
+                    .unwrap()
+                    .power
+            };
+            assert_eq!(power(start), Uint128(600));
+            assert_eq!(power(start + 1), Uint128(700));
+            assert_eq!(power(start + 999), Uint128(700));
+            assert_eq!(power(start + 1_000), Uint128::zero());
+            let total = query_total_voting_power_at(&deps, start + 1_000).unwrap();
+            assert_eq!(total.power, Uint128::zero());
+            let top = query_top_stakers(&deps, None, None).unwrap();
+            assert!(top.stakers.is_empty());
         }
     }
     mod claim_unstake {
@@ -1012,17 +7292,9 @@ mod tests {
             };
             let res = handle(&mut deps, env.clone(), msg.clone()).unwrap();
             // Claim unStaked funds
-            let store = staking_storage(&mut deps.storage)
-                .load(
-                    &deps
-                        .api
-                        .canonical_address(&before_all.default_sender)
-                        .unwrap()
-                        .as_slice(),
-                )
-                .unwrap();
+            let holder = query_holder(&deps, before_all.default_sender.clone()).unwrap();
             let mut env = mock_env(before_all.default_sender.clone(), &[]);
-            env.block.height = store.period + 1;
+            env.block.height = holder.period + 1;
             let msg = HandleMsg::ClaimUnStaked {};
             let res = handle(&mut deps, env.clone(), msg.clone());
             match res {
@@ -1052,17 +7324,9 @@ mod tests {
             };
             let res = handle(&mut deps, env.clone(), msg.clone()).unwrap();
             // Claim unStaked funds
-            let store = staking_storage(&mut deps.storage)
-                .load(
-                    &deps
-                        .api
-                        .canonical_address(&before_all.default_sender)
-                        .unwrap()
-                        .as_slice(),
-                )
-                .unwrap();
+            let holder = query_holder(&deps, before_all.default_sender.clone()).unwrap();
             let mut env = mock_env(before_all.default_sender.clone(), &[]);
-            env.block.height = store.period + 1;
+            env.block.height = holder.period + 1;
             let msg = HandleMsg::ClaimUnStaked {};
             let res = handle(&mut deps, env.clone(), msg.clone()).unwrap();
             assert_eq!(res.messages.len(), 1);
@@ -1088,8 +7352,77 @@ mod tests {
                 .unwrap();
             assert_eq!(store.bonded, Uint128(1_000));
             assert_eq!(store.un_bonded, Uint128::zero());
-            assert_eq!(store.available, Uint128::zero());
-            assert_eq!(store.period, 0);
+            assert!(store.available.is_empty());
+            assert!(store.unbonding.is_empty());
+            let all_bonded = query_all_bonded(&deps).unwrap();
+            assert_eq!(all_bonded.total_bonded, Uint128(1_000));
+            assert_eq!(all_bonded.total_unbonding, Uint128::zero());
+            assert_totals_consistent(&deps);
+        }
+        #[test]
+        fn release_only_matured_entries() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let mut env = mock_env(before_all.default_sender.clone(), &[]);
+            let msg = HandleMsg::Stake {
+                amount: Uint128(2_000),
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
+            let msg = HandleMsg::UnStake {
+                amount: Uint128(300),
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
+            let first_release = env.block.height + 100;
+            // A second unstake doesn't push back the first release
+            env.block.height += 50;
+            let msg = HandleMsg::UnStake {
+                amount: Uint128(200),
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
+            let second_release = env.block.height + 100;
+
+            env.block.height = first_release;
+            let res = handle(&mut deps, env.clone(), HandleMsg::ClaimUnStaked {}).unwrap();
+            assert_eq!(
+                res.messages[0],
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: before_all.default_contract_address.clone(),
+                    msg: Binary::from(
+                        r#"{"transfer":{"recipient":"terra1q88h7ewu6h3am4mxxeqhu3srt7zw4z5s20q007","amount":"300"}}"#
+                            .as_bytes()
+                    ),
+                    send: vec![]
+                })
+            );
+            let holder = query_holder(&deps, before_all.default_sender.clone()).unwrap();
+            assert_eq!(holder.un_bonded, Uint128(200));
+            assert_eq!(holder.period, second_release);
+            assert_eq!(
+                holder.unbonding,
+                vec![UnbondingInfo {
+                    amount: Uint128(200),
+                    release_height: second_release
+                }]
+            );
+
+            let res = handle(&mut deps, env.clone(), HandleMsg::ClaimUnStaked {});
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(
+                        msg,
+                        format!(
+                            "Your unBonded token will be released at block {}",
+                            second_release
+                        )
+                    );
+                }
+                _ => panic!("Unexpected error"),
+            }
+            assert_totals_consistent(&deps);
         }
     }
     mod claim_reward {
@@ -1172,7 +7505,7 @@ mod tests {
                         .as_slice(),
                     |stake| {
                         let mut stake_data = stake.unwrap();
-                        stake_data.available = Uint128(11_000);
+                        stake_data.available = coins(11_000, "uusd");
                         Ok(stake_data)
                     },
                 )
@@ -1216,15 +7549,15 @@ mod tests {
                         .as_slice(),
                     |stake| {
                         let mut stake_data = stake.unwrap();
-                        stake_data.available = Uint128(1_000);
+                        stake_data.available = coins(1_000, "uusd");
                         Ok(stake_data)
                     },
                 )
                 .unwrap();
             assert_eq!(store.bonded, Uint128(2_000));
             assert_eq!(store.un_bonded, Uint128::zero());
-            assert_eq!(store.available, Uint128(1_000));
-            assert_eq!(store.period, 0);
+            assert_eq!(store.available, coins(1_000, "uusd"));
+            assert!(store.unbonding.is_empty());
             let msg = HandleMsg::ClaimReward {};
             let res = handle(&mut deps, env.clone(), msg.clone()).unwrap();
             assert_eq!(res.messages.len(), 1);
@@ -1250,8 +7583,8 @@ mod tests {
                 .unwrap();
             assert_eq!(store.bonded, Uint128(2_000));
             assert_eq!(store.un_bonded, Uint128::zero());
-            assert_eq!(store.available, Uint128::zero());
-            assert_eq!(store.period, 0);
+            assert!(store.available.is_empty());
+            assert!(store.unbonding.is_empty());
         }
     }
     mod payout_reward {
@@ -1347,7 +7680,7 @@ mod tests {
                     msg,
                     backtrace: None,
                 }) => {
-                    assert_eq!(msg, "Send only uusd, extra denom detected");
+                    assert_eq!(msg, "Only uusd is accepted");
                 }
                 _ => panic!("Unexpected error"),
             }
@@ -1381,6 +7714,64 @@ mod tests {
             println!("{:?}", res);
         }
 
+        #[test]
+        fn no_amount_staked_carried() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(
+                before_all.default_length,
+                &[Coin {
+                    denom: "uusd".to_string(),
+                    amount: Uint128(10_000),
+                }],
+            );
+            default_init(&mut deps);
+            let env = mock_env(before_all.default_sender.clone(), &coins(1_000, "uusd"));
+            let res = handle(&mut deps, env.clone(), HandleMsg::PayoutReward {}).unwrap();
+            assert_eq!(
+                res.log[2..],
+                [
+                    LogAttribute {
+                        key: "refund".to_string(),
+                        value: "1000uusd".to_string(),
+                    },
+                    LogAttribute {
+                        key: "carry".to_string(),
+                        value: "".to_string(),
+                    },
+                ]
+            );
+
+            // With carry on the payout waits for stakers
+            let mut state = config(&mut deps.storage).load().unwrap();
+            state.carry_reward_remainder = true;
+            config(&mut deps.storage).save(&state).unwrap();
+            let res = handle(&mut deps, env.clone(), HandleMsg::PayoutReward {}).unwrap();
+            assert_eq!(res.messages, vec![]);
+            assert_eq!(
+                res.log[2..],
+                [
+                    LogAttribute {
+                        key: "refund".to_string(),
+                        value: "".to_string(),
+                    },
+                    LogAttribute {
+                        key: "carry".to_string(),
+                        value: "1000uusd".to_string(),
+                    },
+                ]
+            );
+
+            let env_stake = mock_env(before_all.default_sender.clone(), &[]);
+            let msg = HandleMsg::Stake {
+                amount: Uint128(1_000),
+            };
+            handle(&mut deps, env_stake, msg).unwrap();
+            handle(&mut deps, env, HandleMsg::PayoutReward {}).unwrap();
+            let holder = query_holder(&deps, before_all.default_sender.clone()).unwrap();
+            assert_eq!(holder.available, coins(2_000, "uusd"));
+            assert_eq!(query_config(&deps).unwrap().reward_carry, vec![]);
+        }
+
         #[test]
         fn success_more_rewards_than_total_staked() {
             let before_all = before_all();
@@ -1411,72 +7802,34 @@ mod tests {
                     denom: "uusd".to_string(),
                     amount: total_rewards.clone(),
                 }],
-            );
-            let msg = HandleMsg::PayoutReward {};
-            let res = handle(&mut deps, env.clone(), msg.clone()).unwrap();
-            println!("{:?}", res);
-            assert_eq!(res.messages.len(), 1);
-            assert_eq!(
-                res.messages[0],
-                CosmosMsg::Bank(BankMsg::Send {
-                    from_address: HumanAddr::from("cosmos2contract"),
-                    to_address: before_all.default_contract_address.clone(),
-                    amount: vec![Coin {
-                        denom: "uusd".to_string(),
-                        amount: Uint128(2)
-                    }]
-                })
-            );
-
-            let store = staking_storage(&mut deps.storage)
-                .load(
-                    &deps
-                        .api
-                        .canonical_address(&before_all.default_sender)
-                        .unwrap()
-                        .as_slice(),
-                )
-                .unwrap();
-            println!("{:?}", store);
-            let rewards1 = store.available;
-            assert_eq!(store.bonded, Uint128(2_153));
-            assert_eq!(store.un_bonded, Uint128::zero());
-            assert_eq!(store.available, Uint128(6_748));
-            assert_eq!(store.period, 0);
+            );
+            let msg = HandleMsg::PayoutReward {};
+            let res = handle(&mut deps, env.clone(), msg.clone()).unwrap();
+            println!("{:?}", res);
+            assert_eq!(res.messages.len(), 0);
 
-            let store = staking_storage(&mut deps.storage)
-                .load(
-                    &deps
-                        .api
-                        .canonical_address(&before_all.default_sender_two)
-                        .unwrap()
-                        .as_slice(),
-                )
-                .unwrap();
-            println!("{:?}", store);
-            let rewards2 = store.available;
-            assert_eq!(store.bonded, Uint128(15_345));
-            assert_eq!(store.un_bonded, Uint128::zero());
-            assert_eq!(store.available, Uint128(48_100));
-            assert_eq!(store.period, 0);
+            let holder = query_holder(&deps, before_all.default_sender.clone()).unwrap();
+            let rewards1 = holder.available[0].amount;
+            assert_eq!(holder.bonded, Uint128(2_153));
+            assert_eq!(holder.un_bonded, Uint128::zero());
+            assert_eq!(holder.available, coins(6_748, "uusd"));
+            assert_eq!(holder.period, 0);
 
-            let store = staking_storage(&mut deps.storage)
-                .load(
-                    &deps
-                        .api
-                        .canonical_address(&before_all.default_sender_owner)
-                        .unwrap()
-                        .as_slice(),
-                )
-                .unwrap();
-            println!("{:?}", store);
-            let rewards3 = store.available;
-            assert_eq!(store.bonded, Uint128(22_178));
-            assert_eq!(store.un_bonded, Uint128::zero());
-            assert_eq!(store.available, Uint128(69_518));
-            assert_eq!(store.period, 0);
+            let holder = query_holder(&deps, before_all.default_sender_two.clone()).unwrap();
+            let rewards2 = holder.available[0].amount;
+            assert_eq!(holder.bonded, Uint128(15_345));
+            assert_eq!(holder.un_bonded, Uint128::zero());
+            assert_eq!(holder.available, coins(48_100, "uusd"));
+            assert_eq!(holder.period, 0);
+
+            let holder = query_holder(&deps, before_all.default_sender_owner.clone()).unwrap();
+            let rewards3 = holder.available[0].amount;
+            assert_eq!(holder.bonded, Uint128(22_178));
+            assert_eq!(holder.un_bonded, Uint128::zero());
+            assert_eq!(holder.available, coins(69_518, "uusd"));
+            assert_eq!(holder.period, 0);
 
-            // Assert total shared rewards is equal sub refunds of 1 UST
+            // Rounding dust of 2 UST stays in the contract as fractions owed to holders
             let total_shared_rewards = rewards1.u128() + rewards2.u128() + rewards3.u128();
             assert_eq!(total_rewards.u128() - 2, total_shared_rewards)
         }
@@ -1514,70 +7867,425 @@ mod tests {
             let msg = HandleMsg::PayoutReward {};
             let res = handle(&mut deps, env.clone(), msg.clone()).unwrap();
             println!("{:?}", res);
+            assert_eq!(res.messages.len(), 0);
+
+            let holder = query_holder(&deps, before_all.default_sender.clone()).unwrap();
+            let rewards1 = holder.available[0].amount;
+            assert_eq!(holder.bonded, Uint128(2_153));
+            assert_eq!(holder.available, coins(671, "uusd"));
+
+            let holder = query_holder(&deps, before_all.default_sender_two.clone()).unwrap();
+            let rewards2 = holder.available[0].amount;
+            assert_eq!(holder.bonded, Uint128(15_345));
+            assert_eq!(holder.available, coins(4_783, "uusd"));
+
+            let holder = query_holder(&deps, before_all.default_sender_owner.clone()).unwrap();
+            let rewards3 = holder.available[0].amount;
+            assert_eq!(holder.bonded, Uint128(22_178));
+            assert_eq!(holder.available, coins(6_913, "uusd"));
+
+            let total_shared_rewards = rewards1.u128() + rewards2.u128() + rewards3.u128();
+            assert_eq!(total_rewards.u128() - 1, total_shared_rewards)
+        }
+        #[test]
+        fn rewards_are_conserved_across_payouts() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let holders = [
+                (before_all.default_sender.clone(), Uint128(2_153)),
+                (before_all.default_sender_two.clone(), Uint128(15_345)),
+                (before_all.default_sender_owner.clone(), Uint128(22_178)),
+            ];
+            for (address, amount) in holders.iter() {
+                let env = mock_env(address.clone(), &[]);
+                handle(&mut deps, env, HandleMsg::Stake { amount: *amount }).unwrap();
+            }
+
+            let payouts = [12_368u128, 124_368, 7, 99_991];
+            for payout in payouts.iter() {
+                let env = mock_env(
+                    before_all.default_contract_address.clone(),
+                    &[Coin {
+                        denom: "uusd".to_string(),
+                        amount: Uint128(*payout),
+                    }],
+                );
+                handle(&mut deps, env, HandleMsg::PayoutReward {}).unwrap();
+            }
+            let state = config_read(&deps.storage).load().unwrap();
+            let mut total_scaled = state.reward_indexes[0].remainder.u128();
+            for (address, _) in holders.iter() {
+                let mut stake = staking_storage_read(&deps.storage)
+                    .load(deps.api.canonical_address(address).unwrap().as_slice())
+                    .unwrap();
+                settle_reward(
+                    &state.reward_indexes,
+                    &state.token_reward_indexes,
+                    &mut stake,
+                );
+                total_scaled += stake.available[0].amount.u128() * REWARD_INDEX_SCALE
+                    + stake.reward_indexes[0].remainder.u128();
+            }
+            let total_paid: u128 = payouts.iter().sum();
+            assert_eq!(total_scaled, total_paid * REWARD_INDEX_SCALE);
+        }
+        #[test]
+        fn multi_denom_payout() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(
+                before_all.default_length,
+                &[
+                    Coin {
+                        denom: "uusd".to_string(),
+                        amount: Uint128(10_000),
+                    },
+                    Coin {
+                        denom: "ukrw".to_string(),
+                        amount: Uint128(400_000),
+                    },
+                ],
+            );
+            let init_msg = InitMsg {
+                address_cw20_loterra_smart_contract: before_all.default_contract_address.clone(),
+                unbonded_period: 100,
+                reward_denoms: vec!["uusd".to_string(), "ukrw".to_string()],
+                reward_tokens: vec![],
+                max_unbonding_entries: 3,
+                carry_reward_remainder: false,
+                lock_tiers: vec![],
+                min_bonding_age: 0,
+                timelock_delay: 0,
+                emergency_withdraw_delay: 1_000,
+                max_pause_duration: 50_000,
+                governance: GovernanceConfig {
+                    proposal_min_stake: Uint128(1_000),
+                    quorum: Decimal::percent(30),
+                    vote_threshold: Decimal::percent(50),
+                    voting_period: 100,
+                },
+            };
+            init(
+                &mut deps,
+                mock_env(before_all.default_sender_owner.clone(), &[]),
+                init_msg,
+            )
+            .unwrap();
+            let env = mock_env(before_all.default_sender.clone(), &[]);
+            let msg = HandleMsg::Stake {
+                amount: Uint128(1_000),
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
+            let env_two = mock_env(before_all.default_sender_two.clone(), &[]);
+            let msg = HandleMsg::Stake {
+                amount: Uint128(3_000),
+            };
+            handle(&mut deps, env_two, msg).unwrap();
+
+            let env_payout = mock_env(
+                before_all.default_contract_address.clone(),
+                &[
+                    Coin {
+                        denom: "ukrw".to_string(),
+                        amount: Uint128(400_000),
+                    },
+                    Coin {
+                        denom: "uusd".to_string(),
+                        amount: Uint128(10_000),
+                    },
+                ],
+            );
+            let res = handle(&mut deps, env_payout, HandleMsg::PayoutReward {}).unwrap();
+            assert_eq!(res.messages.len(), 0);
+
+            let holder = query_holder(&deps, before_all.default_sender_two.clone()).unwrap();
+            assert_eq!(
+                holder.available,
+                vec![
+                    Coin {
+                        denom: "uusd".to_string(),
+                        amount: Uint128(7_500),
+                    },
+                    Coin {
+                        denom: "ukrw".to_string(),
+                        amount: Uint128(300_000),
+                    },
+                ]
+            );
+
+            let res = handle(&mut deps, env, HandleMsg::ClaimReward {}).unwrap();
             assert_eq!(res.messages.len(), 1);
             assert_eq!(
                 res.messages[0],
                 CosmosMsg::Bank(BankMsg::Send {
                     from_address: HumanAddr::from("cosmos2contract"),
-                    to_address: before_all.default_contract_address.clone(),
-                    amount: vec![Coin {
-                        denom: "uusd".to_string(),
-                        amount: Uint128(1)
-                    }]
+                    to_address: before_all.default_sender.clone(),
+                    amount: vec![
+                        Coin {
+                            denom: "uusd".to_string(),
+                            amount: Uint128(2_500),
+                        },
+                        Coin {
+                            denom: "ukrw".to_string(),
+                            amount: Uint128(100_000),
+                        },
+                    ]
                 })
             );
+            let holder = query_holder(&deps, before_all.default_sender.clone()).unwrap();
+            assert!(holder.available.is_empty());
+        }
+        #[test]
+        fn late_staker_does_not_earn_past_payouts() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env = mock_env(before_all.default_sender.clone(), &[]);
+            let msg = HandleMsg::Stake {
+                amount: Uint128(1_000),
+            };
+            handle(&mut deps, env, msg).unwrap();
 
-            let store = staking_storage(&mut deps.storage)
-                .load(
-                    &deps
-                        .api
-                        .canonical_address(&before_all.default_sender)
-                        .unwrap()
-                        .as_slice(),
-                )
-                .unwrap();
-            println!("{:?}", store);
-            let rewards1 = store.available;
-            assert_eq!(store.bonded, Uint128(2_153));
-            assert_eq!(store.un_bonded, Uint128::zero());
-            assert_eq!(store.available, Uint128(671));
-            assert_eq!(store.period, 0);
+            let env = mock_env(
+                before_all.default_contract_address.clone(),
+                &[Coin {
+                    denom: "uusd".to_string(),
+                    amount: Uint128(10_000),
+                }],
+            );
+            handle(&mut deps, env.clone(), HandleMsg::PayoutReward {}).unwrap();
 
-            let store = staking_storage(&mut deps.storage)
-                .load(
-                    &deps
-                        .api
-                        .canonical_address(&before_all.default_sender_two)
-                        .unwrap()
-                        .as_slice(),
-                )
-                .unwrap();
-            println!("{:?}", store);
-            let rewards2 = store.available;
-            assert_eq!(store.bonded, Uint128(15_345));
-            assert_eq!(store.un_bonded, Uint128::zero());
-            assert_eq!(store.available, Uint128(4_783));
-            assert_eq!(store.period, 0);
+            let env_two = mock_env(before_all.default_sender_two.clone(), &[]);
+            let msg = HandleMsg::Stake {
+                amount: Uint128(3_000),
+            };
+            handle(&mut deps, env_two, msg).unwrap();
+            handle(&mut deps, env, HandleMsg::PayoutReward {}).unwrap();
 
-            let store = staking_storage(&mut deps.storage)
-                .load(
-                    &deps
-                        .api
-                        .canonical_address(&before_all.default_sender_owner)
-                        .unwrap()
-                        .as_slice(),
-                )
+            let holder = query_holder(&deps, before_all.default_sender.clone()).unwrap();
+            assert_eq!(holder.available, coins(12_500, "uusd"));
+            let holder = query_holder(&deps, before_all.default_sender_two.clone()).unwrap();
+            assert_eq!(holder.available, coins(7_500, "uusd"));
+        }
+
+        #[test]
+        fn carry_reward_remainder() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let mut state = config(&mut deps.storage).load().unwrap();
+            state.carry_reward_remainder = true;
+            config(&mut deps.storage).save(&state).unwrap();
+            // Bonded supply large enough for the index to round off whole units
+            let env = mock_env(before_all.default_sender.clone(), &[]);
+            let msg = HandleMsg::Stake {
+                amount: Uint128(3_000_000_000_000),
+            };
+            handle(&mut deps, env, msg).unwrap();
+
+            let env = mock_env(
+                before_all.default_contract_address.clone(),
+                &coins(10, "uusd"),
+            );
+            let res = handle(&mut deps, env.clone(), HandleMsg::PayoutReward {}).unwrap();
+            assert_eq!(res.messages.len(), 0);
+            assert_eq!(
+                res.log[3],
+                LogAttribute {
+                    key: "carry".to_string(),
+                    value: "1uusd".to_string(),
+                }
+            );
+            let state = query_config(&deps).unwrap();
+            assert_eq!(state.reward_carry, coins(1, "uusd"));
+            let holder = query_holder(&deps, before_all.default_sender.clone()).unwrap();
+            assert_eq!(holder.available, coins(9, "uusd"));
+
+            // The carried unit joins the next payout
+            let res = handle(&mut deps, env, HandleMsg::PayoutReward {}).unwrap();
+            assert_eq!(res.messages.len(), 0);
+            let state = query_config(&deps).unwrap();
+            assert_eq!(state.reward_carry, coins(2, "uusd"));
+            let holder = query_holder(&deps, before_all.default_sender.clone()).unwrap();
+            assert_eq!(holder.available, coins(18, "uusd"));
+        }
+    }
+
+    mod payout_reward_stream {
+        use super::*;
+        // handle_payout_reward_stream
+
+        fn add_stream_payer<S: Storage, A: Api, Q: Querier>(
+            deps: &mut Extern<S, A, Q>,
+            payer: HumanAddr,
+        ) {
+            let before_all = before_all();
+            let env = mock_env(before_all.default_sender_owner, &[]);
+            let msg = HandleMsg::SetStreamPayers {
+                payers: vec![payer],
+            };
+            handle(deps, env, msg).unwrap();
+        }
+
+        #[test]
+        fn only_admin_or_stream_payer() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env = mock_env(before_all.default_sender.clone(), &coins(1_000, "uusd"));
+            let msg = HandleMsg::PayoutRewardStream {
+                start_height: env.block.height,
+                end_height: env.block.height + 100,
+            };
+            let res = handle(&mut deps, env.clone(), msg.clone());
+            match res {
+                Err(StdError::Unauthorized { .. }) => {}
+                _ => panic!("Unexpected error"),
+            }
+
+            add_stream_payer(&mut deps, before_all.default_sender.clone());
+            handle(&mut deps, env, msg.clone()).unwrap();
+            let env = mock_env(
+                before_all.default_sender_owner.clone(),
+                &coins(1_000, "uusd"),
+            );
+            handle(&mut deps, env, msg).unwrap();
+            let streams = query_reward_streams(&deps).unwrap().streams;
+            let payer = deps
+                .api
+                .canonical_address(&before_all.default_sender)
                 .unwrap();
-            println!("{:?}", store);
-            let rewards3 = store.available;
-            assert_eq!(store.bonded, Uint128(22_178));
-            assert_eq!(store.un_bonded, Uint128::zero());
-            assert_eq!(store.available, Uint128(6_913));
-            assert_eq!(store.period, 0);
+            assert_eq!(streams[0].payer, payer);
+        }
 
-            // Assert total shared rewards is equal sub refunds of 1 UST
-            let total_shared_rewards = rewards1.u128() + rewards2.u128() + rewards3.u128();
-            assert_eq!(total_rewards.u128() - 1, total_shared_rewards)
+        #[test]
+        fn invalid_heights() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let env = mock_env(
+                before_all.default_sender_owner.clone(),
+                &coins(1_000, "uusd"),
+            );
+            let msg = HandleMsg::PayoutRewardStream {
+                start_height: env.block.height - 1,
+                end_height: env.block.height + 100,
+            };
+            let res = handle(&mut deps, env.clone(), msg);
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, "Stream can't start in the past");
+                }
+                _ => panic!("Unexpected error"),
+            }
+            let msg = HandleMsg::PayoutRewardStream {
+                start_height: env.block.height,
+                end_height: env.block.height,
+            };
+            let res = handle(&mut deps, env.clone(), msg);
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, "End height must be greater than start height");
+                }
+                _ => panic!("Unexpected error"),
+            }
+        }
+
+        #[test]
+        fn too_many_streams() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            add_stream_payer(&mut deps, before_all.default_sender.clone());
+            let env = mock_env(before_all.default_sender.clone(), &coins(1, "uusd"));
+            let msg = HandleMsg::PayoutRewardStream {
+                start_height: env.block.height,
+                end_height: u64::MAX,
+            };
+            for _ in 0..MAX_REWARD_STREAMS {
+                handle(&mut deps, env.clone(), msg.clone()).unwrap();
+            }
+            let res = handle(&mut deps, env.clone(), msg.clone());
+            match res {
+                Err(GenericErr {
+                    msg,
+                    backtrace: None,
+                }) => {
+                    assert_eq!(msg, "Too many reward streams (max 10)");
+                }
+                _ => panic!("Unexpected error"),
+            }
+
+            // The cap is per payer, the admin can still open streams
+            let env = mock_env(
+                before_all.default_sender_owner.clone(),
+                &coins(1_000, "uusd"),
+            );
+            handle(&mut deps, env, msg).unwrap();
+            let streams = query_reward_streams(&deps).unwrap().streams;
+            assert_eq!(streams.len(), MAX_REWARD_STREAMS + 1);
+        }
+
+        #[test]
+        fn success_linear_release() {
+            let before_all = before_all();
+            let mut deps = mock_dependencies(before_all.default_length, &[]);
+            default_init(&mut deps);
+            let mut env = mock_env(before_all.default_sender.clone(), &[]);
+            let start_height = env.block.height;
+            let msg = HandleMsg::Stake {
+                amount: Uint128(1_000),
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
+
+            add_stream_payer(&mut deps, before_all.default_contract_address.clone());
+            let env_payer = mock_env(
+                before_all.default_contract_address.clone(),
+                &coins(1_000, "uusd"),
+            );
+            let msg = HandleMsg::PayoutRewardStream {
+                start_height,
+                end_height: start_height + 100,
+            };
+            let res = handle(&mut deps, env_payer, msg).unwrap();
+            assert_eq!(res.messages.len(), 0);
+            let streams = query_reward_streams(&deps).unwrap().streams;
+            assert_eq!(streams.len(), 1);
+            assert_eq!(streams[0].amount, Uint128(1_000));
+            assert_eq!(streams[0].released, Uint128::zero());
+            let holder = query_holder(&deps, before_all.default_sender.clone()).unwrap();
+            assert_eq!(holder.available, vec![]);
+            assert_eq!(holder.streaming, coins(1_000, "uusd"));
+
+            // Half of the stream is released before the second staker joins
+            let mut env_two = mock_env(before_all.default_sender_two.clone(), &[]);
+            env_two.block.height = start_height + 50;
+            let msg = HandleMsg::Stake {
+                amount: Uint128(1_000),
+            };
+            handle(&mut deps, env_two.clone(), msg).unwrap();
+            let holder = query_holder(&deps, before_all.default_sender.clone()).unwrap();
+            assert_eq!(holder.available, coins(500, "uusd"));
+            assert_eq!(holder.streaming, coins(250, "uusd"));
+
+            // Releasing past the end height only distributes what is left
+            env.block.height = start_height + 150;
+            let msg = HandleMsg::UnStake {
+                amount: Uint128(1_000),
+            };
+            handle(&mut deps, env, msg).unwrap();
+            let holder = query_holder(&deps, before_all.default_sender.clone()).unwrap();
+            assert_eq!(holder.available, coins(750, "uusd"));
+            assert_eq!(holder.streaming, vec![]);
+            let holder = query_holder(&deps, before_all.default_sender_two.clone()).unwrap();
+            assert_eq!(holder.available, coins(250, "uusd"));
+            assert_eq!(query_reward_streams(&deps).unwrap().streams, vec![]);
         }
     }
 }
@@ -1,5 +1,8 @@
-use crate::state::State;
-use cosmwasm_std::{CanonicalAddr, HumanAddr, Uint128};
+use crate::state::{
+    AdminAction, GovernanceConfig, LockInfo, LockTier, PauseFlags, PenaltyDestination, Proposal,
+    RewardStream, State, UnbondingInfo, Vote, VoteOption, WarmingInfo,
+};
+use cosmwasm_std::{Binary, Coin, Decimal, HumanAddr, Uint128};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
@@ -7,7 +10,40 @@ use serde::{Deserialize, Serialize};
 pub struct InitMsg {
     pub address_cw20_loterra_smart_contract: HumanAddr,
     pub unbonded_period: u64,
-    pub denom_reward: String,
+    pub reward_denoms: Vec<String>,
+    pub reward_tokens: Vec<HumanAddr>,
+    pub max_unbonding_entries: u64,
+    /// Keep payout rounding remainder for the next payout instead of refunding it
+    pub carry_reward_remainder: bool,
+    pub lock_tiers: Vec<LockTier>,
+    /// Blocks a new deposit waits before earning rewards
+    pub min_bonding_age: u64,
+    /// Blocks an admin action waits in the queue before it can be executed
+    pub timelock_delay: u64,
+    /// Blocks locked before holders can withdraw everything with EmergencyWithdraw
+    pub emergency_withdraw_delay: u64,
+    /// Blocks a pause lasts before it lapses, unless the admin extends it
+    pub max_pause_duration: u64,
+    pub governance: GovernanceConfig,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MigrateMsg {
+    /// Parameters missing from the first layout, required to migrate from it
+    pub legacy: Option<LegacyMigrateMsg>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LegacyMigrateMsg {
+    pub reward_tokens: Vec<HumanAddr>,
+    pub max_unbonding_entries: u64,
+    pub carry_reward_remainder: bool,
+    pub lock_tiers: Vec<LockTier>,
+    pub min_bonding_age: u64,
+    pub timelock_delay: u64,
+    pub emergency_withdraw_delay: u64,
+    pub max_pause_duration: u64,
+    pub governance: GovernanceConfig,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -15,48 +51,205 @@ pub struct InitMsg {
 pub enum HandleMsg {
     /// Stake tokens
     Stake { amount: Uint128 },
+    /// CW20 Send hook, the loterra cw-20 calls it with the embedded ReceiveMsg
+    Receive(Cw20ReceiveMsg),
     /// UnStake tokens,
     UnStake { amount: Uint128 },
+    /// UnStake and receive tokens right away, minus the instant unStake penalty
+    InstantUnStake { amount: Uint128 },
+    /// Move unBonding tokens back to bonded, latest released first
+    ReBond { amount: Uint128 },
+    /// Lock bonded tokens for the duration of a lock tier to earn its reward weight
+    Lock { amount: Uint128, tier: u64 },
+    /// Settle a holder whose lock expired, anyone can call it. Locks go back to the base
+    /// reward weight at expiry on their own
+    ExpireLock { address: HumanAddr },
     /// Claim reward
     ClaimReward {},
-    /// Claim unStaked tokens, available after unBonded period
+    /// Claim unStaked tokens of every entry whose unBonded period ended
     ClaimUnStaked {},
-    /// LoTerra payout staking reward
+    /// Withdraw bonded, unBonding and rewards at once, once locked for emergency_withdraw_delay
+    EmergencyWithdraw {},
+    /// LoTerra payout staking reward, any whitelisted reward denoms
     PayoutReward {},
+    /// Admin or stream payer payout staking reward released linearly from start to end height
+    PayoutRewardStream { start_height: u64, end_height: u64 },
     /// Admin
-    /// Security owner can switch on off to prevent exploit
+    /// Security owner can switch on off to prevent exploit, applied right away
+    /// Pause every operation, or resume them all when everything is paused
+    /// The guardian can call it too, only to pause
     SafeLock {},
+    /// Pause or resume each operation, applied right away
+    /// The guardian can call it too, only to pause more operations
+    SetPauseFlags { flags: PauseFlags },
+    /// Push the current pause expiry to max_pause_duration blocks from now, applied right away
+    ExtendPause {},
+    /// Admin actions below are queued for timelock_delay blocks before they apply
     /// Admin renounce and restore contract address to admin for full decentralization
     Renounce {},
+    /// Admin propose a new admin, it becomes admin once it accepts
+    ProposeNewAdmin {
+        address: HumanAddr,
+        expiry: Option<u64>,
+    },
+    /// Proposed admin accept the admin role, admin actions queued before are dropped
+    AcceptAdmin {},
+    /// Admin cancel the pending admin proposal, applied right away
+    CancelAdminProposal {},
+    /// Admin set or remove the guardian, removing is applied right away
+    SetGuardian { guardian: Option<HumanAddr> },
+    /// Admin set the addresses allowed to open reward streams besides the admin
+    SetStreamPayers { payers: Vec<HumanAddr> },
+    /// Admin set the instant unStake penalty rate and where the penalty goes
+    SetInstantUnStakePenalty {
+        rate: Decimal,
+        destination: PenaltyDestination,
+        treasury: Option<HumanAddr>,
+    },
+    /// Admin update the given parameters, others are left unchanged
+    /// reward_denoms and reward_tokens must keep every whitelisted entry, they can only be added
+    UpdateConfig {
+        unbonded_period: Option<u64>,
+        reward_denoms: Option<Vec<String>>,
+        reward_tokens: Option<Vec<HumanAddr>>,
+        address_cw20_loterra_smart_contract: Option<HumanAddr>,
+        max_unbonding_entries: Option<u64>,
+        carry_reward_remainder: Option<bool>,
+        lock_tiers: Option<Vec<LockTier>>,
+        min_bonding_age: Option<u64>,
+        timelock_delay: Option<u64>,
+        emergency_withdraw_delay: Option<u64>,
+        max_pause_duration: Option<u64>,
+        /// Boxed to keep the message size down
+        governance: Option<Box<GovernanceConfig>>,
+    },
+    /// Admin rebuild the total bonded and unBonding counters from every holder
+    RecomputeTotals {},
+    /// Apply a queued admin action once its delay passed, anyone can call it
+    ExecuteAdminAction { id: u64 },
+    /// Admin drop a queued admin action
+    CancelAdminAction { id: u64 },
+    /// Governance
+    /// Create a proposal, msg is an UpdateConfig applied if it passes, None for text only
+    CreateProposal {
+        description: String,
+        msg: Option<Box<HandleMsg>>,
+    },
+    /// Vote with the bonded amount at the proposal start height
+    CastVote {
+        proposal_id: u64,
+        option: VoteOption,
+    },
+    /// Tally a proposal once its voting period ended, anyone can call it
+    EndProposal { proposal_id: u64 },
+    /// Apply the config changes of a passed proposal, anyone can call it
+    ExecuteProposal { proposal_id: u64 },
+}
+
+/// Payload of a CW20 Send, sender is the owner of the sent tokens
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Cw20ReceiveMsg {
+    pub sender: HumanAddr,
+    pub amount: Uint128,
+    pub msg: Option<Binary>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceiveMsg {
+    /// Stake the sent tokens
+    Stake {},
+    /// Payout staking reward in a whitelisted cw-20 token
+    PayoutReward {},
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum QueryMsg {
-    /// Get config state
+    /// Get config state, pause keeps the flags after a lapse, PauseAt gives the ones in effect
     Config {},
     /// Get specific holder, address and balance
     GetHolder { address: HumanAddr },
     /// Get specific all bonded tokens
     GetAllBonded {},
-    /// Not used to be called directly
+    /// List holders by address, at most MAX_HOLDERS_LIMIT per page
+    GetHolders {
+        start_after: Option<HumanAddr>,
+        limit: Option<u32>,
+        order: Option<OrderBy>,
+    },
+    /// Get reward streams not fully distributed
+    RewardStreams {},
+    /// Get queued admin actions
+    PendingAdminActions {},
+    /// Get since when withdrawals are locked and from which height EmergencyWithdraw works
+    EmergencyStatus {},
+    /// Get the operations paused at a height, nothing is paused once pause_expiry is reached
+    PauseAt { height: u64 },
+    /// Get the bonded amount of a holder at the end of a height
+    VotingPowerAt { address: HumanAddr, height: u64 },
+    /// Get the total bonded at the end of a height
+    TotalVotingPowerAt { height: u64 },
+    /// Get a proposal
+    Proposal { proposal_id: u64 },
+    /// List holders by descending bonded amount, start_after is the last holder of the previous page
+    TopStakers {
+        limit: Option<u32>,
+        start_after: Option<HumanAddr>,
+    },
+    /// Get the vote of an address on a proposal
+    Vote {
+        proposal_id: u64,
+        address: HumanAddr,
+    },
+}
+
+/// Execute messages of the loterra cw-20 sent by this contract
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Cw20ExecuteMsg {
+    /// Transfer tokens held by this contract
+    Transfer {
+        recipient: HumanAddr,
+        amount: Uint128,
+    },
+    /// Transfer tokens from owner using an allowance given to this contract
     TransferFrom {
         owner: HumanAddr,
         recipient: HumanAddr,
         amount: Uint128,
     },
-    /// Not used to be called directly
-    Transfer {
-        recipient: HumanAddr,
+    /// Transfer tokens to a contract and trigger its Receive hook
+    Send {
+        contract: HumanAddr,
         amount: Uint128,
+        msg: Option<Binary>,
     },
+    /// Destroy tokens held by this contract
+    Burn { amount: Uint128 },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderBy {
+    Asc,
+    Desc,
 }
 
 // We define a custom struct for each query response
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct GetBondedResponse {
-    pub address: CanonicalAddr,
+    pub address: HumanAddr,
     pub bonded: Uint128,
+    pub un_bonded: Uint128,
+    pub available: Vec<Coin>,
+    /// Release height of the next unBonding entry, 0 if none
+    pub period: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct GetHoldersResponse {
+    pub holders: Vec<GetBondedResponse>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -64,14 +257,84 @@ pub struct GetHolderResponse {
     pub address: HumanAddr,
     pub bonded: Uint128,
     pub un_bonded: Uint128,
-    pub available: Uint128,
+    pub available: Vec<Coin>,
+    pub available_tokens: Vec<TokenBalanceResponse>,
+    /// Share of the reward streams not distributed yet, at the current bonded amount
+    pub streaming: Vec<Coin>,
+    /// Release height of the next unBonding entry, 0 if none
     pub period: u64,
+    pub unbonding: Vec<UnbondingInfo>,
+    pub lock: Option<LockInfo>,
+    /// Bonded amount with the locked part counted at its weight
+    pub weighted: Uint128,
+    /// Part of bonded earning rewards
+    pub eligible: Uint128,
+    /// Deposits still warming up, not earning rewards
+    pub warming: Vec<WarmingInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TokenBalanceResponse {
+    pub token: HumanAddr,
+    pub amount: Uint128,
 }
 
 // We define a custom struct for each query response
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct GetAllBondedResponse {
     pub total_bonded: Uint128,
+    pub total_unbonding: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RewardStreamsResponse {
+    pub streams: Vec<RewardStream>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PendingAdminActionsResponse {
+    pub actions: Vec<AdminAction>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct EmergencyStatusResponse {
+    pub locked_since: Option<u64>,
+    pub emergency_withdraw_delay: u64,
+    /// Height from which EmergencyWithdraw works, None while withdrawals aren't locked or the pause lapses first
+    pub withdrawal_height: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PauseResponse {
+    /// Operations paused at the queried height
+    pub pause: PauseFlags,
+    pub pause_expiry: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct VotingPowerResponse {
+    pub height: u64,
+    pub power: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TopStakerResponse {
+    pub address: HumanAddr,
+    pub bonded: Uint128,
+    /// Share of the total bonded
+    pub share: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TopStakersResponse {
+    pub stakers: Vec<TopStakerResponse>,
+}
+
+pub type ProposalResponse = Proposal;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct VoteResponse {
+    pub vote: Option<Vote>,
 }
 
 pub type ConfigResponse = State;
@@ -1,17 +1,265 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::{CanonicalAddr, Storage, Uint128};
+use crate::msg::HandleMsg;
+use cosmwasm_std::{CanonicalAddr, Coin, Decimal, Storage, Uint128};
 use cosmwasm_storage::{
     bucket, bucket_read, singleton, singleton_read, Bucket, ReadonlyBucket, ReadonlySingleton,
     Singleton,
 };
 
 pub static CONFIG_KEY: &[u8] = b"config";
+const CONTRACT_INFO_KEY: &[u8] = b"contract_info";
 const STAKING_KEY: &[u8] = b"staking";
+const WARMING_KEY: &[u8] = b"warming";
+const MATURED_KEY: &[u8] = b"matured";
+const LOCK_EXPIRY_KEY: &[u8] = b"lock_expiry";
+const EXPIRED_LOCK_KEY: &[u8] = b"expired_lock";
+const ADMIN_ACTION_KEY: &[u8] = b"admin_action";
+const VOTING_POWER_KEY: &[u8] = b"voting_power";
+const TOTAL_VOTING_POWER_KEY: &[u8] = b"total_voting_power";
+const PROPOSAL_KEY: &[u8] = b"proposal";
+const VOTE_KEY: &[u8] = b"vote";
+const BONDED_INDEX_KEY: &[u8] = b"bonded_index";
+
+/// Precision of the reward per bonded token index
+pub const REWARD_INDEX_SCALE: u128 = 1_000_000_000_000;
+/// Maximum reward streams running at the same time for each payer
+pub const MAX_REWARD_STREAMS: usize = 10;
+/// Maximum warming up deposits per holder
+pub const MAX_WARMING_ENTRIES: usize = 10;
+/// Maximum length of a proposal description
+pub const MAX_DESCRIPTION_LENGTH: usize = 1024;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct State {
+    pub admin: CanonicalAddr,
+    /// Set by the admin, can only pause operations
+    pub guardian: Option<CanonicalAddr>,
+    pub address_cw20_loterra_smart_contract: CanonicalAddr,
+    pub unbonded_period: u64,
+    /// Operations paused by the admin, only in effect below pause_expiry
+    pub pause: PauseFlags,
+    /// Height at which the current pause was engaged
+    pub paused_since: Option<u64>,
+    /// Height at which the current pause lapses unless the admin extends it
+    /// Kept once lapsed, the guardian can't pause again for max_pause_duration blocks
+    pub pause_expiry: Option<u64>,
+    /// Blocks a pause lasts before it lapses, also added by each extension
+    pub max_pause_duration: u64,
+    /// Height since which holders can't withdraw, None while they can
+    /// Kept through a lapse and shifted by the unlocked blocks if withdrawals are locked again soon
+    pub locked_since: Option<u64>,
+    /// Height the withdrawal lock lapsed at, None while it is engaged or was lifted by the admin
+    pub lock_lapsed_at: Option<u64>,
+    /// Blocks locked before holders can withdraw everything with EmergencyWithdraw
+    pub emergency_withdraw_delay: u64,
+    /// Maximum pending unBonding entries per holder
+    pub max_unbonding_entries: u64,
+    /// Sum of every holder bonded amount
+    pub total_bonded: Uint128,
+    /// Sum of every holder bonded amount weighted by its lock tier, rewards are shared on it
+    pub total_weighted: Uint128,
+    /// Sum of every holder amount waiting for the end of the unBonded period
+    pub total_unbonding: Uint128,
+    /// Sum of bonded deposits not eligible for rewards yet, excluded from total_weighted
+    pub total_warming: Uint128,
+    /// Blocks a new deposit waits before earning rewards
+    pub min_bonding_age: u64,
+    /// Whitelisted reward denoms with their distribution index
+    pub reward_indexes: Vec<RewardIndex>,
+    /// Whitelisted reward cw-20 tokens with their distribution index
+    pub token_reward_indexes: Vec<TokenRewardIndex>,
+    /// Native rewards released linearly over a block range
+    pub reward_streams: Vec<RewardStream>,
+    /// Addresses allowed to open reward streams besides the admin
+    pub stream_payers: Vec<CanonicalAddr>,
+    /// Keep payout rounding remainder in reward_carry instead of refunding the payer
+    pub carry_reward_remainder: bool,
+    /// Payout rounding remainder added to the next payout of the same denom
+    pub reward_carry: Vec<Coin>,
+    /// Lock terms holders can choose, referenced by their position
+    pub lock_tiers: Vec<LockTier>,
+    /// Share of an instant unStake kept as penalty
+    pub instant_unstake_penalty: Decimal,
+    pub penalty_destination: PenaltyDestination,
+    /// Receiver of the penalty with the Treasury destination
+    pub treasury: Option<CanonicalAddr>,
+    /// Admin proposed by the current one, waiting for its acceptance
+    pub pending_admin: Option<PendingAdmin>,
+    /// Blocks an admin action waits in the queue before it can be executed
+    pub timelock_delay: u64,
+    /// Id of the next queued admin action
+    pub next_admin_action_id: u64,
+    pub governance: GovernanceConfig,
+    /// Id of the next proposal
+    pub next_proposal_id: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct GovernanceConfig {
+    /// Bonded amount needed to create a proposal
+    pub proposal_min_stake: Uint128,
+    /// Share of the total voting power that must vote for a proposal to pass
+    pub quorum: Decimal,
+    /// Share of the votes that must be yes for a proposal to pass
+    pub vote_threshold: Decimal,
+    /// Blocks a proposal is open for votes
+    pub voting_period: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Proposal {
+    pub id: u64,
+    pub proposer: CanonicalAddr,
+    pub description: String,
+    /// UpdateConfig applied once the proposal passed, None for a text proposal
+    pub msg: Option<HandleMsg>,
+    /// Voting power is the bonded amount at the end of this height
+    pub start_height: u64,
+    /// Last height votes are accepted
+    pub end_height: u64,
+    /// Total voting power at start_height, base of the quorum, set once the proposal ended
+    pub total_power: Uint128,
+    pub yes: Uint128,
+    pub no: Uint128,
+    pub status: ProposalStatus,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ProposalStatus {
+    Open,
+    Passed,
+    Rejected,
+    /// Passed and its config changes applied
+    Executed,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum VoteOption {
+    Yes,
+    No,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Vote {
+    pub option: VoteOption,
+    pub power: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct AdminAction {
+    pub id: u64,
+    pub msg: HandleMsg,
+    /// Height from which anyone can execute the action
+    pub execute_after: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PendingAdmin {
+    pub address: CanonicalAddr,
+    /// Height from which the proposal can't be accepted anymore
+    pub expiry: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PenaltyDestination {
+    /// Shared between the remaining stakers as a LOTA reward
+    Redistribute,
+    /// Destroyed with the cw-20 Burn
+    Burn,
+    /// Sent to the treasury address
+    Treasury,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LockTier {
+    /// Lock duration in blocks
+    pub duration: u64,
+    /// Reward weight of the locked amount, at least 1
+    pub weight: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct PauseFlags {
+    /// Stake, ReBond and lock changes
+    pub stake: bool,
+    /// UnStake and InstantUnStake
+    pub unstake: bool,
+    pub claim_reward: bool,
+    pub claim_unstake: bool,
+    /// Native and cw-20 reward payouts
+    pub payout: bool,
+}
+
+impl PauseFlags {
+    /// Whether holders can't get their tokens or rewards out
+    pub fn blocks_withdrawal(&self) -> bool {
+        self.unstake || self.claim_unstake || self.claim_reward
+    }
+
+    /// Whether every operation paused in other is also paused here
+    pub fn contains(&self, other: &PauseFlags) -> bool {
+        (self.stake || !other.stake)
+            && (self.unstake || !other.unstake)
+            && (self.claim_reward || !other.claim_reward)
+            && (self.claim_unstake || !other.claim_unstake)
+            && (self.payout || !other.payout)
+    }
+
+    pub fn all() -> Self {
+        PauseFlags {
+            stake: true,
+            unstake: true,
+            claim_reward: true,
+            claim_unstake: true,
+            payout: true,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RewardIndex {
+    pub denom: String,
+    /// Cumulative reward per bonded token, scaled by REWARD_INDEX_SCALE
+    pub index: Uint128,
+    /// Scaled amount not distributed yet
+    pub remainder: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RewardStream {
+    /// Opened the stream, counted against its own MAX_REWARD_STREAMS
+    pub payer: CanonicalAddr,
+    pub denom: String,
+    pub amount: Uint128,
+    /// Part of amount already distributed to the reward index
+    pub released: Uint128,
+    pub start_height: u64,
+    pub end_height: u64,
+}
+
+/// Name and version of the code that last wrote the storage, absent before the first migration support
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ContractInfo {
+    pub contract: String,
+    pub version: String,
+}
+
+pub fn contract_info<S: Storage>(storage: &mut S) -> Singleton<'_, S, ContractInfo> {
+    singleton(storage, CONTRACT_INFO_KEY)
+}
+
+pub fn contract_info_read<S: Storage>(storage: &S) -> ReadonlySingleton<'_, S, ContractInfo> {
+    singleton_read(storage, CONTRACT_INFO_KEY)
+}
+
+/// State of the first layout, stored without a contract info
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LegacyState {
     pub admin: CanonicalAddr,
     pub address_cw20_loterra_smart_contract: CanonicalAddr,
     pub unbonded_period: u64,
@@ -19,26 +267,228 @@ pub struct State {
     pub safe_lock: bool,
 }
 
-pub fn config<S: Storage>(storage: &mut S) -> Singleton<S, State> {
+pub fn legacy_config_read<S: Storage>(storage: &S) -> ReadonlySingleton<'_, S, LegacyState> {
+    singleton_read(storage, CONFIG_KEY)
+}
+
+/// Holder of the first layout, a single unBonding amount and rewards in denom_reward
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LegacyStakingInfo {
+    pub bonded: Uint128,
+    pub un_bonded: Uint128,
+    /// Release height of un_bonded
+    pub period: u64,
+    pub available: Uint128,
+}
+
+pub fn legacy_staking_storage_read<T: Storage>(
+    storage: &T,
+) -> ReadonlyBucket<'_, T, LegacyStakingInfo> {
+    bucket_read(STAKING_KEY, storage)
+}
+
+pub fn config<S: Storage>(storage: &mut S) -> Singleton<'_, S, State> {
     singleton(storage, CONFIG_KEY)
 }
 
-pub fn config_read<S: Storage>(storage: &S) -> ReadonlySingleton<S, State> {
+pub fn config_read<S: Storage>(storage: &S) -> ReadonlySingleton<'_, S, State> {
     singleton_read(storage, CONFIG_KEY)
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TokenRewardIndex {
+    pub token: CanonicalAddr,
+    /// Cumulative reward per bonded token, scaled by REWARD_INDEX_SCALE
+    pub index: Uint128,
+    /// Scaled amount smaller than one unit, not distributed yet
+    pub remainder: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TokenBalance {
+    pub token: CanonicalAddr,
+    pub amount: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct UnbondingInfo {
+    pub amount: Uint128,
+    pub release_height: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct WarmingInfo {
+    pub amount: Uint128,
+    pub eligible_height: u64,
+}
+
+/// Deposits of every holder becoming eligible at the same height
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct WarmingBatch {
+    pub amount: Uint128,
+    /// Holders with a deposit in the batch
+    pub holders: u64,
+}
+
+/// Locks of every holder expiring at the same height
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LockExpiryBatch {
+    /// Locked amount of the batch
+    pub amount: Uint128,
+    /// Locked amount of the batch counted at the tier weights
+    pub weighted: Uint128,
+    /// Holders with a lock in the batch
+    pub holders: u64,
+}
+
+/// Reward indexes when a warming batch became eligible or a lock batch expired, kept until
+/// its holders settle
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MaturedBatch {
+    /// Holders with a deposit in the batch not settled yet
+    pub holders: u64,
+    pub reward_indexes: Vec<RewardIndex>,
+    pub token_reward_indexes: Vec<TokenRewardIndex>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LockInfo {
+    pub tier: u64,
+    /// Part of bonded that can't be unStaked before expiry
+    pub amount: Uint128,
+    pub expiry: u64,
+    /// Reward weight of the tier when the lock was made
+    pub weight: Decimal,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct StakingInfo {
     pub bonded: Uint128,
+    /// Sum of every pending unBonding entry
     pub un_bonded: Uint128,
-    pub period: u64,
-    pub available: Uint128,
+    /// Pending unBonding entries, each released once its height is reached
+    pub unbonding: Vec<UnbondingInfo>,
+    /// Settled rewards per denom, only non-zero amounts
+    pub available: Vec<Coin>,
+    /// Global reward indexes at the last settlement of this holder
+    pub reward_indexes: Vec<RewardIndex>,
+    /// Settled cw-20 token rewards, only non-zero amounts
+    pub available_tokens: Vec<TokenBalance>,
+    /// Global token reward indexes at the last settlement of this holder
+    pub token_reward_indexes: Vec<TokenRewardIndex>,
+    /// Locked part of bonded, earning rewards at its tier weight
+    pub lock: Option<LockInfo>,
+    /// Part of bonded deposited less than min_bonding_age blocks ago, earning no rewards
+    pub warming: Vec<WarmingInfo>,
 }
 
-pub fn staking_storage<T: Storage>(storage: &mut T) -> Bucket<T, StakingInfo> {
+pub fn staking_storage<T: Storage>(storage: &mut T) -> Bucket<'_, T, StakingInfo> {
     bucket(STAKING_KEY, storage)
 }
 
-pub fn staking_storage_read<T: Storage>(storage: &T) -> ReadonlyBucket<T, StakingInfo> {
+pub fn staking_storage_read<T: Storage>(storage: &T) -> ReadonlyBucket<'_, T, StakingInfo> {
     bucket_read(STAKING_KEY, storage)
 }
+
+/// Warming batches keyed by their big endian eligible height
+pub fn warming_storage<T: Storage>(storage: &mut T) -> Bucket<'_, T, WarmingBatch> {
+    bucket(WARMING_KEY, storage)
+}
+
+pub fn warming_storage_read<T: Storage>(storage: &T) -> ReadonlyBucket<'_, T, WarmingBatch> {
+    bucket_read(WARMING_KEY, storage)
+}
+
+/// Matured batches keyed by their big endian eligible height
+pub fn matured_storage<T: Storage>(storage: &mut T) -> Bucket<'_, T, MaturedBatch> {
+    bucket(MATURED_KEY, storage)
+}
+
+pub fn matured_storage_read<T: Storage>(storage: &T) -> ReadonlyBucket<'_, T, MaturedBatch> {
+    bucket_read(MATURED_KEY, storage)
+}
+
+/// Lock batches keyed by their big endian expiry
+pub fn lock_expiry_storage<T: Storage>(storage: &mut T) -> Bucket<'_, T, LockExpiryBatch> {
+    bucket(LOCK_EXPIRY_KEY, storage)
+}
+
+pub fn lock_expiry_storage_read<T: Storage>(storage: &T) -> ReadonlyBucket<'_, T, LockExpiryBatch> {
+    bucket_read(LOCK_EXPIRY_KEY, storage)
+}
+
+/// Expired lock batches keyed by their big endian expiry
+pub fn expired_lock_storage<T: Storage>(storage: &mut T) -> Bucket<'_, T, MaturedBatch> {
+    bucket(EXPIRED_LOCK_KEY, storage)
+}
+
+pub fn expired_lock_storage_read<T: Storage>(storage: &T) -> ReadonlyBucket<'_, T, MaturedBatch> {
+    bucket_read(EXPIRED_LOCK_KEY, storage)
+}
+
+/// Queued admin actions keyed by their big endian id
+pub fn admin_action_storage<T: Storage>(storage: &mut T) -> Bucket<'_, T, AdminAction> {
+    bucket(ADMIN_ACTION_KEY, storage)
+}
+
+pub fn admin_action_storage_read<T: Storage>(storage: &T) -> ReadonlyBucket<'_, T, AdminAction> {
+    bucket_read(ADMIN_ACTION_KEY, storage)
+}
+
+/// Bonded amount of a holder at the end of each height it changed, keyed by big endian height
+pub fn voting_power_storage<'a, T: Storage>(
+    storage: &'a mut T,
+    address: &CanonicalAddr,
+) -> Bucket<'a, T, Uint128> {
+    Bucket::multilevel(&[VOTING_POWER_KEY, address.as_slice()], storage)
+}
+
+pub fn voting_power_storage_read<'a, T: Storage>(
+    storage: &'a T,
+    address: &CanonicalAddr,
+) -> ReadonlyBucket<'a, T, Uint128> {
+    ReadonlyBucket::multilevel(&[VOTING_POWER_KEY, address.as_slice()], storage)
+}
+
+/// Total bonded at the end of each height it changed, keyed by big endian height
+pub fn total_voting_power_storage<T: Storage>(storage: &mut T) -> Bucket<'_, T, Uint128> {
+    bucket(TOTAL_VOTING_POWER_KEY, storage)
+}
+
+pub fn total_voting_power_storage_read<T: Storage>(storage: &T) -> ReadonlyBucket<'_, T, Uint128> {
+    bucket_read(TOTAL_VOTING_POWER_KEY, storage)
+}
+
+/// Proposals keyed by their big endian id
+pub fn proposal_storage<T: Storage>(storage: &mut T) -> Bucket<'_, T, Proposal> {
+    bucket(PROPOSAL_KEY, storage)
+}
+
+pub fn proposal_storage_read<T: Storage>(storage: &T) -> ReadonlyBucket<'_, T, Proposal> {
+    bucket_read(PROPOSAL_KEY, storage)
+}
+
+/// Votes of a proposal keyed by voter
+pub fn vote_storage<T: Storage>(storage: &mut T, proposal_id: u64) -> Bucket<'_, T, Vote> {
+    Bucket::multilevel(&[VOTE_KEY, &proposal_id.to_be_bytes()], storage)
+}
+
+pub fn vote_storage_read<T: Storage>(storage: &T, proposal_id: u64) -> ReadonlyBucket<'_, T, Vote> {
+    ReadonlyBucket::multilevel(&[VOTE_KEY, &proposal_id.to_be_bytes()], storage)
+}
+
+/// Holders with a bonded amount keyed by big endian bonded followed by their address
+pub fn bonded_index_storage<T: Storage>(storage: &mut T) -> Bucket<'_, T, CanonicalAddr> {
+    bucket(BONDED_INDEX_KEY, storage)
+}
+
+pub fn bonded_index_storage_read<T: Storage>(storage: &T) -> ReadonlyBucket<'_, T, CanonicalAddr> {
+    bucket_read(BONDED_INDEX_KEY, storage)
+}
+
+/// Key of a holder in the bonded index
+pub fn bonded_index_key(bonded: Uint128, address: &CanonicalAddr) -> Vec<u8> {
+    let mut key = bonded.u128().to_be_bytes().to_vec();
+    key.extend_from_slice(address.as_slice());
+    key
+}